@@ -0,0 +1,194 @@
+use crate::atomic;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+pub const UNCATEGORIZED: &str = "uncategorized";
+
+const DEFAULT_CATEGORIES: &[(&str, &[&str])] = &[
+    ("food", &["dining", "restaurant", "restaurants", "takeaway"]),
+    ("groceries", &["grocery", "supermarket"]),
+    ("transport", &["transit", "taxi", "fuel", "parking"]),
+    ("housing", &["rent", "mortgage"]),
+    ("utilities", &["bills", "electricity", "internet", "phone"]),
+    ("entertainment", &["fun", "movies", "games"]),
+    ("health", &["medical", "pharmacy", "doctor"]),
+    ("travel", &["trip", "hotel", "flights"]),
+    ("shopping", &["clothes", "clothing"]),
+    (UNCATEGORIZED, &["misc", "other"]),
+];
+
+#[derive(Debug, Clone)]
+pub struct Category {
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+pub struct Categories {
+    path: PathBuf,
+    entries: Vec<Category>,
+    /// Names the config requires, which `remove` cannot take away.
+    configured: Vec<String>,
+}
+
+pub fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "\"{}\" must be a single word of letters, digits, '-' or '_'",
+            name
+        ));
+    }
+    Ok(name)
+}
+
+impl Categories {
+    /// Reads the categories file, or starts from `defaults` (the built-in list if `None`)
+    /// when it does not exist yet. Names in `defaults` the file lacks are added to it, so
+    /// the config's list keeps applying after the file is first written.
+    pub fn load(path: &Path, defaults: Option<&[String]>) -> Result<Categories, String> {
+        let mut categories = Categories {
+            path: path.to_path_buf(),
+            entries: Vec::new(),
+            configured: defaults.map(<[String]>::to_vec).unwrap_or_default(),
+        };
+
+        if !path.exists() {
+            categories.entries = match defaults {
+                Some(names) => names
+                    .iter()
+                    .map(|name| Category {
+                        name: name.clone(),
+                        aliases: Vec::new(),
+                    })
+                    .collect(),
+                None => DEFAULT_CATEGORIES
+                    .iter()
+                    .map(|(name, aliases)| Category {
+                        name: name.to_string(),
+                        aliases: aliases.iter().map(|a| a.to_string()).collect(),
+                    })
+                    .collect(),
+            };
+            categories.ensure_uncategorized();
+            return Ok(categories);
+        }
+
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            if name.starts_with('#') {
+                continue;
+            }
+            let name = validate_name(name)
+                .map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))?;
+            let aliases = words
+                .map(validate_name)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))?;
+            categories.entries.push(Category { name, aliases });
+        }
+        for name in defaults.into_iter().flatten() {
+            if categories.resolve(name).is_none() {
+                categories.entries.push(Category {
+                    name: name.clone(),
+                    aliases: Vec::new(),
+                });
+            }
+        }
+
+        categories.ensure_uncategorized();
+        Ok(categories)
+    }
+
+    fn ensure_uncategorized(&mut self) {
+        if self.find(UNCATEGORIZED).is_none() {
+            self.entries.push(Category {
+                name: UNCATEGORIZED.to_string(),
+                aliases: Vec::new(),
+            });
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut contents = String::new();
+        for category in &self.entries {
+            contents.push_str(&category.name);
+            for alias in &category.aliases {
+                contents.push(' ');
+                contents.push_str(alias);
+            }
+            contents.push('\n');
+        }
+        atomic::write_file(&self.path, contents.as_bytes())
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+
+    pub fn entries(&self) -> &[Category] {
+        &self.entries
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|c| c.name == name)
+    }
+
+    /// Maps a category name or alias, in any case, to its canonical name.
+    pub fn resolve(&self, input: &str) -> Option<&str> {
+        let input = input.trim().to_lowercase();
+        self.entries
+            .iter()
+            .find(|c| c.name == input || c.aliases.contains(&input))
+            .map(|c| c.name.as_str())
+    }
+
+    pub fn add(&mut self, name: &str) -> Result<String, String> {
+        let name = validate_name(name)?;
+        if self.resolve(&name).is_some() {
+            return Err(format!("category or alias \"{}\" already exists", name));
+        }
+        self.entries.push(Category {
+            name: name.clone(),
+            aliases: Vec::new(),
+        });
+        Ok(name)
+    }
+
+    pub fn add_alias(&mut self, alias: &str, name: &str) -> Result<(String, String), String> {
+        let alias = validate_name(alias)?;
+        if self.resolve(&alias).is_some() {
+            return Err(format!("category or alias \"{}\" already exists", alias));
+        }
+        let target = self
+            .resolve(name)
+            .map(str::to_string)
+            .ok_or_else(|| format!("unknown category \"{}\"", name))?;
+        let index = self.find(&target).unwrap();
+        self.entries[index].aliases.push(alias.clone());
+        Ok((alias, target))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<String, String> {
+        let name = name.trim().to_lowercase();
+        if name == UNCATEGORIZED {
+            return Err(format!("\"{}\" cannot be removed", UNCATEGORIZED));
+        }
+        if self.configured.contains(&name) {
+            return Err(format!(
+                "\"{}\" is listed in the config's categories; change that setting first",
+                name
+            ));
+        }
+        match self.find(&name) {
+            Some(index) => Ok(self.entries.remove(index).name),
+            None => Err(format!("unknown category \"{}\"", name)),
+        }
+    }
+}
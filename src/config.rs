@@ -0,0 +1,478 @@
+use crate::atomic;
+use crate::categories::validate_name;
+use crate::dates::Timezone;
+use crate::import::ImportProfile;
+use crate::money::Currency;
+use crate::output::OutputFormat;
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const APP_DIR: &str = "rusty-expense-tracker";
+const CONFIG_FILE: &str = "config.toml";
+pub const DEFAULT_DATA_FILE: &str = "expenses.txt";
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+/// Name under which the unnamed ledger (`data_file` or the XDG default) is listed.
+pub const DEFAULT_LEDGER: &str = "default";
+
+/// Columns of the `list` table whose width can be configured, with their default widths.
+pub const COLUMNS: &[(&str, usize)] = &[
+    ("id", 6),
+    ("date", 12),
+    ("description", 18),
+    ("amount", 14),
+    ("currency", 5),
+    ("category", 16),
+];
+const MAX_COLUMN_WIDTH: usize = 200;
+
+pub const KEYS: &[&str] = &[
+    "data_file",
+    "currency",
+    "timezone",
+    "date_format",
+    "categories",
+    "output",
+    "columns.<name>",
+];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Ledger to use when neither `--file` nor `EXPENSES_FILE` is given. Relative paths
+    /// are taken from the directory holding the config file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    /// Categories that always exist, and the whole list while there is no categories file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub columns: BTreeMap<String, usize>,
+    /// Named ledgers selectable with `--ledger`, relative to the config directory like
+    /// `data_file`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ledgers: BTreeMap<String, PathBuf>,
+    /// Saved column mappings for `import csv --profile`, one per bank.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub imports: BTreeMap<String, ImportProfile>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rusty-expense-tracker/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+    }
+
+    /// Reads and validates the config file, treating a missing file as an empty config.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut config = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<Config>(&contents)
+                .map_err(|e| format!("{}: {}", path.display(), e.to_string().trim_end()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        config.path = Some(path.to_path_buf());
+        config
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Err("no config file location; set HOME or XDG_CONFIG_HOME".to_string());
+        };
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        atomic::write_file(path, contents.as_bytes())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(code) = &self.currency {
+            parse_currency(code)?;
+        }
+        if let Some(timezone) = &self.timezone {
+            Timezone::parse(timezone)?;
+        }
+        if let Some(format) = &self.date_format {
+            validate_date_format(format)?;
+        }
+        if let Some(output) = &self.output {
+            OutputFormat::parse(output)?;
+        }
+        for name in self.categories.iter().flatten() {
+            validate_name(name)?;
+        }
+        for (column, width) in &self.columns {
+            validate_column(column, *width)?;
+        }
+        for name in self.ledgers.keys() {
+            validate_ledger_name(name)?;
+        }
+        for (name, profile) in &self.imports {
+            validate_name(name)?;
+            profile
+                .validate()
+                .map_err(|e| format!("import profile \"{}\": {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        let path = expand_home(path);
+        match self.path.as_deref().and_then(Path::parent) {
+            Some(dir) => dir.join(path),
+            None => path,
+        }
+    }
+
+    pub fn data_file(&self) -> Option<PathBuf> {
+        self.data_file.as_deref().map(|path| self.resolve(path))
+    }
+
+    /// The file behind a named ledger, including the unnamed default one.
+    pub fn ledger(&self, name: &str) -> Option<PathBuf> {
+        if name == DEFAULT_LEDGER {
+            return self.data_file().or_else(default_data_file);
+        }
+        self.ledgers.get(name).map(|path| self.resolve(path))
+    }
+
+    /// Every ledger by name, starting with the default one.
+    pub fn ledgers(&self) -> Vec<(String, PathBuf)> {
+        let mut ledgers: Vec<(String, PathBuf)> = self
+            .ledger(DEFAULT_LEDGER)
+            .map(|path| (DEFAULT_LEDGER.to_string(), path))
+            .into_iter()
+            .collect();
+        ledgers.extend(
+            self.ledgers
+                .iter()
+                .map(|(name, path)| (name.clone(), self.resolve(path))),
+        );
+        ledgers
+    }
+
+    /// Registers a ledger, placing it beside the default ledger unless `path` is given.
+    pub fn add_ledger(&mut self, name: &str, path: Option<&Path>) -> Result<PathBuf, String> {
+        let name = validate_ledger_name(name)?;
+        if self.ledger(&name).is_some() {
+            return Err(format!("ledger \"{}\" already exists", name));
+        }
+        let path = match path {
+            Some(path) => absolute(path)?,
+            None => default_data_file()
+                .and_then(|file| Some(file.parent()?.join(format!("{}.txt", name))))
+                .ok_or("no data directory; set HOME or XDG_DATA_HOME, or pass --path")?,
+        };
+        if self.ledgers().iter().any(|(_, existing)| *existing == path) {
+            return Err(format!("{} is already registered", path.display()));
+        }
+        self.ledgers.insert(name, path.clone());
+        Ok(path)
+    }
+
+    /// Points whichever ledger uses `old` at `new`, returning its name.
+    pub fn move_ledger(&mut self, old: &Path, new: &Path) -> Option<String> {
+        let (name, _) = self.ledgers().into_iter().find(|(_, path)| path == old)?;
+        let new = absolute(new).unwrap_or_else(|_| new.to_path_buf());
+        if name == DEFAULT_LEDGER {
+            self.data_file = Some(new);
+        } else {
+            self.ledgers.insert(name.clone(), new);
+        }
+        Some(name)
+    }
+
+    pub fn rename_ledger(&mut self, old: &str, new: &str) -> Result<String, String> {
+        let new = validate_ledger_name(new)?;
+        if self.ledger(&new).is_some() {
+            return Err(format!("ledger \"{}\" already exists", new));
+        }
+        let path = self.take_ledger(old)?;
+        self.ledgers.insert(new.clone(), path);
+        Ok(new)
+    }
+
+    pub fn remove_ledger(&mut self, name: &str) -> Result<PathBuf, String> {
+        let path = self.take_ledger(name)?;
+        Ok(self.resolve(&path))
+    }
+
+    fn take_ledger(&mut self, name: &str) -> Result<PathBuf, String> {
+        if name == DEFAULT_LEDGER {
+            return Err(format!(
+                "the \"{}\" ledger is set through data_file",
+                DEFAULT_LEDGER
+            ));
+        }
+        self.ledgers
+            .remove(name)
+            .ok_or_else(|| unknown_ledger(name))
+    }
+
+    pub fn import_profile(&self, name: &str) -> Result<&ImportProfile, String> {
+        self.imports.get(name).ok_or_else(|| {
+            format!(
+                "unknown import profile \"{}\"; see `import profile list`",
+                name
+            )
+        })
+    }
+
+    /// Stores `profile` under `name`, replacing any profile saved before.
+    pub fn save_import_profile(
+        &mut self,
+        name: &str,
+        profile: ImportProfile,
+    ) -> Result<String, String> {
+        let name = validate_name(name)?;
+        self.imports.insert(name.clone(), profile);
+        Ok(name)
+    }
+
+    pub fn remove_import_profile(&mut self, name: &str) -> Result<ImportProfile, String> {
+        self.import_profile(name)?;
+        Ok(self.imports.remove(name).unwrap_or_default())
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+            .as_deref()
+            .and_then(Currency::from_code)
+            .unwrap_or_default()
+    }
+
+    pub fn date_format(&self) -> &str {
+        self.date_format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT)
+    }
+
+    pub fn output(&self) -> OutputFormat {
+        self.output
+            .as_deref()
+            .and_then(|name| OutputFormat::parse(name).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn column_width(&self, column: &str) -> usize {
+        self.columns.get(column).copied().unwrap_or_else(|| {
+            COLUMNS
+                .iter()
+                .find(|(name, _)| *name == column)
+                .map_or(0, |(_, width)| *width)
+        })
+    }
+
+    /// The value stored in the config file for `key`, if any.
+    pub fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let value = match key {
+            "data_file" => self.data_file.as_ref().map(|p| p.display().to_string()),
+            "currency" => self.currency.clone(),
+            "timezone" => self.timezone.clone(),
+            "date_format" => self.date_format.clone(),
+            "categories" => self.categories.as_ref().map(|names| names.join(",")),
+            "output" => self.output.clone(),
+            _ => {
+                let column = column_key(key)?;
+                self.columns.get(column).map(usize::to_string)
+            }
+        };
+        Ok(value)
+    }
+
+    /// The value in effect for `key`, falling back to the built-in default.
+    pub fn effective(&self, key: &str) -> Result<String, String> {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = match key {
+            "data_file" => default_data_file()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_FILE))
+                .display()
+                .to_string(),
+            "currency" => self.currency().to_string(),
+            "timezone" => "local".to_string(),
+            "date_format" => DEFAULT_DATE_FORMAT.to_string(),
+            "categories" => "(built-in list)".to_string(),
+            "output" => self.output().to_string(),
+            _ => self.column_width(column_key(key)?).to_string(),
+        };
+        Ok(value)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "data_file" => {
+                self.data_file = Some(absolute(Path::new(value))?);
+            }
+            "currency" => self.currency = Some(parse_currency(value)?.to_string()),
+            "timezone" => {
+                Timezone::parse(value)?;
+                self.timezone = Some(value.to_string());
+            }
+            "date_format" => {
+                validate_date_format(value)?;
+                self.date_format = Some(value.to_string());
+            }
+            "categories" => {
+                let names = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(validate_name)
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.categories = Some(names);
+            }
+            "output" => self.output = Some(OutputFormat::parse(value)?.to_string()),
+            _ => {
+                let column = column_key(key)?;
+                let width = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("\"{}\" is not a column width", value))?;
+                validate_column(column, width)?;
+                self.columns.insert(column.to_string(), width);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<(), String> {
+        match key {
+            "data_file" => self.data_file = None,
+            "currency" => self.currency = None,
+            "timezone" => self.timezone = None,
+            "date_format" => self.date_format = None,
+            "categories" => self.categories = None,
+            "output" => self.output = None,
+            _ => {
+                self.columns.remove(column_key(key)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every key `get` and `set` accept, with the column widths spelled out.
+    pub fn all_keys() -> Vec<String> {
+        let mut keys: Vec<String> = KEYS[..KEYS.len() - 1]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        keys.extend(COLUMNS.iter().map(|(name, _)| format!("columns.{}", name)));
+        keys
+    }
+}
+
+fn column_key(key: &str) -> Result<&str, String> {
+    key.strip_prefix("columns.")
+        .filter(|column| COLUMNS.iter().any(|(name, _)| name == column))
+        .ok_or_else(|| {
+            format!(
+                "unknown config key \"{}\", expected one of {}",
+                key,
+                KEYS.join(", ")
+            )
+        })
+}
+
+fn validate_ledger_name(name: &str) -> Result<String, String> {
+    let name = validate_name(name)?;
+    if name == DEFAULT_LEDGER {
+        return Err(format!("\"{}\" is reserved for the data_file ledger", name));
+    }
+    Ok(name)
+}
+
+pub fn unknown_ledger(name: &str) -> String {
+    format!("unknown ledger \"{}\"; see `ledger list`", name)
+}
+
+fn validate_column(column: &str, width: usize) -> Result<(), String> {
+    if !COLUMNS.iter().any(|(name, _)| *name == column) {
+        let names: Vec<&str> = COLUMNS.iter().map(|(name, _)| *name).collect();
+        return Err(format!(
+            "unknown column \"{}\", expected one of {}",
+            column,
+            names.join(", ")
+        ));
+    }
+    if width == 0 || width > MAX_COLUMN_WIDTH {
+        return Err(format!(
+            "column width {} for \"{}\" must be between 1 and {}",
+            width, column, MAX_COLUMN_WIDTH
+        ));
+    }
+    Ok(())
+}
+
+fn parse_currency(code: &str) -> Result<Currency, String> {
+    Currency::from_code(code).ok_or_else(|| format!("unknown currency \"{}\"", code))
+}
+
+fn validate_date_format(format: &str) -> Result<(), String> {
+    if format.trim().is_empty() || StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("\"{}\" is not a valid date format", format));
+    }
+    Ok(())
+}
+
+/// `$XDG_DATA_HOME/rusty-expense-tracker/expenses.txt`, falling back to `~/.local/share`.
+pub fn default_data_file() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join(APP_DIR).join(DEFAULT_DATA_FILE))
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        // The spec says relative values are invalid and should be ignored.
+        Some(dir) if Path::new(&dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => home_dir().map(|home| home.join(fallback)),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Makes a path given on the command line independent of the current directory.
+fn absolute(path: &Path) -> Result<PathBuf, String> {
+    let path = expand_home(path);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        env::current_dir().map_err(|e| e.to_string())?.join(path)
+    };
+    Ok(path
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect())
+}
+
+/// Expands a leading `~/` so config files can point into the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,390 @@
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use std::fmt;
+
+/// The timezone used to decide what "today" is: the system's local zone unless an IANA
+/// zone such as `Australia/Sydney` is configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timezone {
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl Timezone {
+    pub fn parse(name: &str) -> Result<Timezone, String> {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+        name.parse::<Tz>()
+            .map(Timezone::Named)
+            .map_err(|_| format!("\"{}\" is not a known IANA timezone", name))
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        match self {
+            Timezone::Local => Local::now().date_naive(),
+            Timezone::Named(tz) => Utc::now().with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// The wall-clock time in this zone at the instant `time`.
+    pub fn local_time(&self, time: DateTime<FixedOffset>) -> NaiveDateTime {
+        match self {
+            Timezone::Local => time.with_timezone(&Local).naive_local(),
+            Timezone::Named(tz) => time.with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+/// Parses a date given on the command line relative to `today`. Accepts ISO dates
+/// (`2025-07-14`), `today`, `yesterday`, weekday names (`friday`, `last fri`) and offsets
+/// such as `-3d`, `-2w` or `-1m`.
+pub fn parse_date_arg(input: &str, today: NaiveDate) -> Result<NaiveDate, String> {
+    let invalid = || {
+        format!(
+            "\"{}\" is not a valid date; use YYYY-MM-DD, today, yesterday, \
+             last <weekday> or an offset such as -3d",
+            input
+        )
+    };
+    let text = input.trim().to_lowercase();
+
+    if let Ok(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        return Ok(date);
+    }
+
+    match text.as_str() {
+        "today" => return Ok(today),
+        "yesterday" => return today.checked_sub_days(Days::new(1)).ok_or_else(invalid),
+        _ => {}
+    }
+
+    if let Some(offset) = text.strip_prefix('-') {
+        let split = offset
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (count, unit) = offset.split_at(split);
+        let count: u32 = count.parse().map_err(|_| invalid())?;
+        let date = match unit {
+            "d" | "day" | "days" => today.checked_sub_days(Days::new(count as u64)),
+            "w" | "week" | "weeks" => (count as u64)
+                .checked_mul(7)
+                .and_then(|days| today.checked_sub_days(Days::new(days))),
+            "m" | "month" | "months" => today.checked_sub_months(Months::new(count)),
+            "y" | "year" | "years" => count
+                .checked_mul(12)
+                .and_then(|months| today.checked_sub_months(Months::new(months))),
+            _ => None,
+        };
+        return date.ok_or_else(invalid);
+    }
+
+    let (strictly_before, name) = match text.strip_prefix("last ") {
+        Some(name) => (true, name.trim()),
+        None => (false, text.as_str()),
+    };
+    let weekday: Weekday = name.parse().map_err(|_| invalid())?;
+    let mut days_back =
+        (7 + today.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+    if strictly_before && days_back == 0 {
+        days_back = 7;
+    }
+    today
+        .checked_sub_days(Days::new(days_back as u64))
+        .ok_or_else(invalid)
+}
+
+/// Parses an age such as `30d` or `2w` into the date that many days before `today`. Dates
+/// in any form accepted by [`parse_date_arg`] are taken as they are.
+pub fn parse_age(input: &str, today: NaiveDate) -> Result<NaiveDate, String> {
+    let text = input.trim();
+    if text.starts_with(|c: char| c.is_ascii_digit()) && !text.contains('-') {
+        return parse_date_arg(&format!("-{}", text), today);
+    }
+    parse_date_arg(text, today)
+}
+
+/// An inclusive range of dates; either end may be open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn between(from: NaiveDate, to: NaiveDate) -> DateRange {
+        DateRange {
+            from: Some(from),
+            to: Some(to),
+        }
+    }
+
+    pub fn month(year: i32, month: u32) -> Option<DateRange> {
+        let from = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let to = from.checked_add_months(Months::new(1))?.pred_opt()?;
+        Some(DateRange::between(from, to))
+    }
+
+    pub fn quarter(year: i32, quarter: u32) -> Option<DateRange> {
+        let from = NaiveDate::from_ymd_opt(year, (quarter - 1) * 3 + 1, 1)?;
+        let to = from.checked_add_months(Months::new(3))?.pred_opt()?;
+        Some(DateRange::between(from, to))
+    }
+
+    pub fn year(year: i32) -> Option<DateRange> {
+        Some(DateRange::between(
+            NaiveDate::from_ymd_opt(year, 1, 1)?,
+            NaiveDate::from_ymd_opt(year, 12, 31)?,
+        ))
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+impl fmt::Display for DateRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => {
+                if DateRange::year(from.year()) == Some(*self) {
+                    write!(f, "for {}", from.year())
+                } else if DateRange::quarter(from.year(), from.month0() / 3 + 1) == Some(*self) {
+                    write!(f, "for {}-Q{}", from.year(), from.month0() / 3 + 1)
+                } else if DateRange::month(from.year(), from.month()) == Some(*self) {
+                    write!(f, "for {}", from.format("%Y-%m"))
+                } else {
+                    write!(f, "from {} to {}", from, to)
+                }
+            }
+            (Some(from), None) => write!(f, "since {}", from),
+            (None, Some(to)) => write!(f, "until {}", to),
+            (None, None) => write!(f, "for all time"),
+        }
+    }
+}
+
+/// Collects the date-range flags shared by commands that select expenses by period:
+/// `--year`, `--month`, `--from`/`--to` and shortcuts such as `--this-month` or `--ytd`.
+#[derive(Debug, Default)]
+pub struct RangeOptions {
+    year: Option<String>,
+    month: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    shortcut: Option<String>,
+}
+
+impl RangeOptions {
+    pub const SHORTCUTS: &[&str] = &[
+        "--this-month",
+        "--last-month",
+        "--this-quarter",
+        "--last-quarter",
+        "--this-year",
+        "--last-year",
+        "--ytd",
+    ];
+
+    /// Consumes `flag` (and `value`, when the flag takes one) if it is a range flag,
+    /// returning how many arguments were used.
+    pub fn take(&mut self, flag: &str, value: Option<&String>) -> usize {
+        if Self::SHORTCUTS.contains(&flag) {
+            self.shortcut = Some(flag.to_string());
+            return 1;
+        }
+        let slot = match flag {
+            "--year" => &mut self.year,
+            "--month" => &mut self.month,
+            "--from" => &mut self.from,
+            "--to" => &mut self.to,
+            _ => return 0,
+        };
+        match value {
+            Some(value) => {
+                *slot = Some(value.clone());
+                2
+            }
+            None => 0,
+        }
+    }
+
+    pub fn resolve(&self, today: NaiveDate) -> Result<DateRange, String> {
+        let calendar = self.year.is_some() || self.month.is_some();
+        let explicit = self.from.is_some() || self.to.is_some();
+        if [calendar, explicit, self.shortcut.is_some()]
+            .iter()
+            .filter(|&&set| set)
+            .count()
+            > 1
+        {
+            return Err(
+                "use only one of --year/--month, --from/--to or a shortcut such as --ytd"
+                    .to_string(),
+            );
+        }
+
+        if let Some(shortcut) = &self.shortcut {
+            return shortcut_range(shortcut, today);
+        }
+
+        if explicit {
+            let from = self
+                .from
+                .as_deref()
+                .map(|d| parse_date_arg(d, today))
+                .transpose()?;
+            let to = self
+                .to
+                .as_deref()
+                .map(|d| parse_date_arg(d, today))
+                .transpose()?;
+            if let (Some(from), Some(to)) = (from, to)
+                && from > to
+            {
+                return Err(format!("--from {} is after --to {}", from, to));
+            }
+            return Ok(DateRange { from, to });
+        }
+
+        let year = match &self.year {
+            Some(year) => Some(
+                year.trim()
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|y| (1..=9999).contains(y))
+                    .ok_or_else(|| format!("\"{}\" is not a valid year", year))?,
+            ),
+            None => None,
+        };
+
+        match &self.month {
+            Some(month) => {
+                let invalid = || format!("\"{}\" is not a valid month; use 1-12 or YYYY-MM", month);
+                let (month_year, number) = match month.trim().split_once('-') {
+                    Some((y, m)) => (Some(y.parse::<i32>().map_err(|_| invalid())?), m),
+                    None => (None, month.trim()),
+                };
+                if let (Some(month_year), Some(year)) = (month_year, year)
+                    && month_year != year
+                {
+                    return Err(format!("--month {} is outside --year {}", month, year));
+                }
+                let number: u32 = number.parse().map_err(|_| invalid())?;
+                let year = month_year.or(year).unwrap_or(today.year());
+                DateRange::month(year, number).ok_or_else(invalid)
+            }
+            None => match year {
+                Some(year) => DateRange::year(year).ok_or_else(|| format!("invalid year {}", year)),
+                None => Ok(DateRange::default()),
+            },
+        }
+    }
+}
+
+fn shortcut_range(shortcut: &str, today: NaiveDate) -> Result<DateRange, String> {
+    let quarter = today.month0() / 3 + 1;
+    let last_month = today
+        .with_day(1)
+        .and_then(|d| d.checked_sub_months(Months::new(1)));
+    let range = match shortcut {
+        "--this-month" => DateRange::month(today.year(), today.month()),
+        "--last-month" => last_month.and_then(|d| DateRange::month(d.year(), d.month())),
+        "--this-quarter" => DateRange::quarter(today.year(), quarter),
+        "--last-quarter" if quarter == 1 => DateRange::quarter(today.year() - 1, 4),
+        "--last-quarter" => DateRange::quarter(today.year(), quarter - 1),
+        "--this-year" => DateRange::year(today.year()),
+        "--last-year" => DateRange::year(today.year() - 1),
+        "--ytd" => {
+            NaiveDate::from_ymd_opt(today.year(), 1, 1).map(|from| DateRange::between(from, today))
+        }
+        _ => None,
+    };
+    range.ok_or_else(|| format!("cannot compute {} from {}", shortcut, today))
+}
+
+/// The buckets a report groups expenses into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Period {
+    pub fn parse(name: &str) -> Result<Period, String> {
+        match name.trim().to_lowercase().as_str() {
+            "week" | "weekly" => Ok(Period::Week),
+            "month" | "monthly" => Ok(Period::Month),
+            "quarter" | "quarterly" => Ok(Period::Quarter),
+            "year" | "yearly" => Ok(Period::Year),
+            _ => Err(format!(
+                "\"{}\" is not a period; use week, month, quarter or year",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Quarter => "quarter",
+            Period::Year => "year",
+        }
+    }
+
+    /// The first day of the period containing `date`.
+    pub fn start_of(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Period::Month => date.with_day(1).unwrap(),
+            Period::Quarter => {
+                NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap()
+            }
+            Period::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        }
+    }
+
+    /// The first day of the period after the one starting on `start`.
+    pub fn next(&self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Week => start.checked_add_days(Days::new(7)),
+            Period::Month => start.checked_add_months(Months::new(1)),
+            Period::Quarter => start.checked_add_months(Months::new(3)),
+            Period::Year => start.checked_add_months(Months::new(12)),
+        }
+    }
+
+    pub fn label(&self, start: NaiveDate) -> String {
+        match self {
+            Period::Week => {
+                let week = start.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Period::Month => start.format("%Y-%m").to_string(),
+            Period::Quarter => format!("{}-Q{}", start.year(), start.month0() / 3 + 1),
+            Period::Year => start.year().to_string(),
+        }
+    }
+
+    /// The start of every period overlapping `from..=to`.
+    pub fn starts_between(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let mut starts = Vec::new();
+        let mut start = Some(self.start_of(from));
+        while let Some(current) = start.filter(|s| *s <= to) {
+            starts.push(current);
+            start = self.next(current);
+        }
+        starts
+    }
+}
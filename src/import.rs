@@ -0,0 +1,364 @@
+use crate::Expense;
+use crate::money::{Currency, Money};
+use chrono::NaiveDate;
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// How a statement writes money going out of the account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sign {
+    /// Spending is negative, as on most bank statements; positive rows are credits.
+    #[default]
+    Negative,
+    /// Spending is positive; negative rows are refunds.
+    Positive,
+}
+
+impl Sign {
+    pub fn parse(name: &str) -> Result<Sign, String> {
+        match name.trim().to_lowercase().as_str() {
+            "negative" => Ok(Sign::Negative),
+            "positive" => Ok(Sign::Positive),
+            _ => Err(format!(
+                "unknown sign convention \"{}\", expected negative or positive",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Sign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Sign::Negative => "negative",
+            Sign::Positive => "positive",
+        })
+    }
+}
+
+/// Where a bank's CSV export keeps each field. Columns are header names or 1-based
+/// positions; anything left unset falls back to the command line or the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sign: Option<Sign>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Whether the first row names the columns; defaults to true.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<bool>,
+}
+
+impl ImportProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(format) = &self.date_format
+            && (format.trim().is_empty()
+                || StrftimeItems::new(format).any(|item| item == Item::Error))
+        {
+            return Err(format!("\"{}\" is not a valid date format", format));
+        }
+        if let Some(code) = &self.currency
+            && Currency::from_code(code).is_none()
+        {
+            return Err(format!("unknown currency \"{}\"", code));
+        }
+        if self.header == Some(false) {
+            for column in [&self.date, &self.description, &self.amount]
+                .into_iter()
+                .flatten()
+            {
+                if column.parse::<usize>().is_err() {
+                    return Err(format!(
+                        "column \"{}\" needs a header row; use its position instead",
+                        column
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub const FIELDS: [&str; 8] = [
+        "date",
+        "description",
+        "amount",
+        "sign",
+        "date_format",
+        "currency",
+        "category",
+        "header",
+    ];
+
+    /// The values of `FIELDS`, `None` where the profile leaves them unset.
+    pub fn fields(&self) -> [Option<String>; 8] {
+        [
+            self.date.clone(),
+            self.description.clone(),
+            self.amount.clone(),
+            self.sign.map(|sign| sign.to_string()),
+            self.date_format.clone(),
+            self.currency.clone(),
+            self.category.clone(),
+            self.header.map(|header| header.to_string()),
+        ]
+    }
+
+    /// This profile with every field `other` sets taken from `other`.
+    pub fn overlay(&self, other: &ImportProfile) -> ImportProfile {
+        ImportProfile {
+            date: other.date.clone().or_else(|| self.date.clone()),
+            description: other
+                .description
+                .clone()
+                .or_else(|| self.description.clone()),
+            amount: other.amount.clone().or_else(|| self.amount.clone()),
+            sign: other.sign.or(self.sign),
+            date_format: other
+                .date_format
+                .clone()
+                .or_else(|| self.date_format.clone()),
+            currency: other.currency.clone().or_else(|| self.currency.clone()),
+            category: other.category.clone().or_else(|| self.category.clone()),
+            header: other.header.or(self.header),
+        }
+    }
+}
+
+/// Collects the column mapping flags: `--date COL`, `--description COL`, `--amount COL`,
+/// `--sign negative|positive`, `--date-format FMT`, `--currency CODE`, `--category NAME`
+/// and `--no-header`.
+#[derive(Debug, Default)]
+pub struct ImportOptions {
+    mapping: ImportProfile,
+    sign: Option<String>,
+}
+
+impl ImportOptions {
+    pub fn take(&mut self, flag: &str, value: Option<&String>) -> usize {
+        let slot = match flag {
+            "--no-header" => {
+                self.mapping.header = Some(false);
+                return 1;
+            }
+            "--date" => &mut self.mapping.date,
+            "--description" => &mut self.mapping.description,
+            "--amount" => &mut self.mapping.amount,
+            "--sign" => &mut self.sign,
+            "--date-format" => &mut self.mapping.date_format,
+            "--currency" => &mut self.mapping.currency,
+            "--category" => &mut self.mapping.category,
+            _ => return 0,
+        };
+        match value {
+            Some(value) => {
+                *slot = Some(value.clone());
+                2
+            }
+            None => 0,
+        }
+    }
+
+    /// The flags as a profile to lay over a saved one.
+    pub fn resolve(&self) -> Result<ImportProfile, String> {
+        let mut mapping = self.mapping.clone();
+        mapping.sign = self.sign.as_deref().map(Sign::parse).transpose()?;
+        mapping.validate()?;
+        Ok(mapping)
+    }
+}
+
+/// One spending row read from a statement.
+#[derive(Debug, Clone)]
+pub struct StatementRow {
+    pub line: usize,
+    pub date: NaiveDate,
+    pub description: String,
+    pub amount: Money,
+}
+
+#[derive(Debug, Default)]
+pub struct Statement {
+    pub rows: Vec<StatementRow>,
+    /// Credits, refunds and zero amounts, which are not expenses.
+    pub skipped: usize,
+}
+
+/// Reads the spending rows of a bank statement. `date_format` and `currency` apply when
+/// the profile does not set them.
+pub fn read_csv(
+    path: &Path,
+    profile: &ImportProfile,
+    date_format: &str,
+    currency: Currency,
+) -> Result<Statement, String> {
+    let error = |e: csv::Error| format!("{}: {}", path.display(), e);
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(profile.header.unwrap_or(true))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(error)?;
+    let headers = if profile.header.unwrap_or(true) {
+        Some(reader.headers().map_err(error)?.clone())
+    } else {
+        None
+    };
+
+    let column = |name: &str, spec: &Option<String>| -> Result<usize, String> {
+        let Some(spec) = spec else {
+            return Err(format!("no {} column; pass --{} COLUMN", name, name));
+        };
+        if let Ok(position) = spec.parse::<usize>() {
+            return position
+                .checked_sub(1)
+                .ok_or_else(|| format!("column positions start at 1, not {}", spec));
+        }
+        let headers = headers
+            .as_ref()
+            .ok_or_else(|| format!("column \"{}\" needs a header row; use its position", spec))?;
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(spec.trim()))
+            .ok_or_else(|| {
+                format!(
+                    "no column \"{}\" in {}; the columns are {}",
+                    spec,
+                    path.display(),
+                    headers.iter().collect::<Vec<_>>().join(", ")
+                )
+            })
+    };
+    let date_column = column("date", &profile.date)?;
+    let description_column = column("description", &profile.description)?;
+    let amount_column = column("amount", &profile.amount)?;
+    let date_format = profile.date_format.as_deref().unwrap_or(date_format);
+    let currency = profile
+        .currency
+        .as_deref()
+        .and_then(Currency::from_code)
+        .unwrap_or(currency);
+    let sign = profile.sign.unwrap_or_default();
+
+    let mut statement = Statement::default();
+    for record in reader.records() {
+        let record = record.map_err(error)?;
+        let line = record.position().map_or(0, |p| p.line() as usize);
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let at = |e: String| format!("{}:{}: {}", path.display(), line, e);
+        let field = |index: usize| {
+            record
+                .get(index)
+                .ok_or_else(|| at(format!("missing column {}", index + 1)))
+        };
+
+        let date = field(date_column)?;
+        let date = NaiveDate::parse_from_str(date, date_format).map_err(|_| {
+            at(format!(
+                "\"{}\" does not match the date format \"{}\"",
+                date, date_format
+            ))
+        })?;
+        let amount = parse_amount(field(amount_column)?, currency).map_err(at)?;
+        let amount = match sign {
+            Sign::Negative => Money::from_minor(-amount.minor(), currency),
+            Sign::Positive => amount,
+        };
+        if !amount.is_positive() {
+            statement.skipped += 1;
+            continue;
+        }
+        let description = field(description_column)?.to_string();
+        if description.is_empty() {
+            return Err(at("empty description".to_string()));
+        }
+        statement.rows.push(StatementRow {
+            line,
+            date,
+            description,
+            amount,
+        });
+    }
+    Ok(statement)
+}
+
+/// Parses an amount as banks print it: `-1,234.50`, `$12.00`, `+3.20` or `(45.00)`.
+fn parse_amount(text: &str, currency: Currency) -> Result<Money, String> {
+    let (negative, text) = match text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        Some(inner) => (true, inner),
+        None => (false, text),
+    };
+    let cleaned: String = text
+        .replace(currency.symbol().trim(), "")
+        .chars()
+        .filter(|c| *c != ',' && *c != '+' && !c.is_whitespace())
+        .collect();
+    let amount = Money::parse(&cleaned, currency).map_err(|e| e.to_string())?;
+    if negative {
+        Ok(Money::from_minor(-amount.minor(), currency))
+    } else {
+        Ok(amount)
+    }
+}
+
+/// For each row, the existing expense it duplicates: same date and amount and a similar
+/// description. Each expense is matched at most once, so a statement can still hold two
+/// identical coffees on the same day when only one was entered by hand.
+pub fn find_duplicates(rows: &[StatementRow], expenses: &[Expense]) -> Vec<Option<i32>> {
+    let mut matched = HashSet::new();
+    rows.iter()
+        .map(|row| {
+            let duplicate = expenses.iter().find(|e| {
+                !e.is_trashed()
+                    && !matched.contains(&e.id)
+                    && e.date == row.date
+                    && e.amount == row.amount
+                    && similar(&e.description, &row.description)
+            })?;
+            matched.insert(duplicate.id);
+            Some(duplicate.id)
+        })
+        .collect()
+}
+
+/// Descriptions count as the same when they start with the same word or share most of
+/// their letter pairs, ignoring case, digits and punctuation. Bank descriptions carry
+/// store numbers and locations a hand-typed one lacks.
+fn similar(a: &str, b: &str) -> bool {
+    let words = |text: &str| -> Vec<String> {
+        text.split(|c: char| !c.is_alphabetic())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.first().is_some_and(|word| b.first() == Some(word)) {
+        return true;
+    }
+    let pairs = |words: &[String]| -> BTreeSet<(char, char)> {
+        let letters: Vec<char> = words.join(" ").chars().collect();
+        letters.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    };
+    let (a, b) = (pairs(&a), pairs(&b));
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    let shared = a.intersection(&b).count();
+    shared * 10 >= (a.len() + b.len()) * 3
+}
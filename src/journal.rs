@@ -0,0 +1,264 @@
+use crate::Expense;
+use crate::record;
+use chrono::{DateTime, FixedOffset, Local};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "# rusty-expense-tracker journal v1";
+const TAIL_WINDOW: u64 = 64 * 1024;
+
+/// One expense as it was before and after an operation; `None` on either side means the
+/// expense did not exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub before: Option<Expense>,
+    pub after: Option<Expense>,
+}
+
+impl Change {
+    pub fn id(&self) -> i32 {
+        self.before
+            .as_ref()
+            .or(self.after.as_ref())
+            .map_or(0, |e| e.id)
+    }
+
+    pub fn inverse(&self) -> Change {
+        Change {
+            before: self.after.clone(),
+            after: self.before.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A command that changed the ledger, by name.
+    Command(String),
+    Undo(u64),
+    Redo(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub seq: u64,
+    pub time: DateTime<FixedOffset>,
+    pub action: Action,
+    pub changes: Vec<Change>,
+}
+
+/// The operations applied to a ledger, kept beside it in an append-only file with one line
+/// per changed expense: `SEQ TIME ACTION TARGET BEFORE AFTER`, tab-separated, where the
+/// expenses are escaped ledger records and lines sharing a sequence number form one entry.
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn path_for(ledger: &Path) -> PathBuf {
+        crate::sibling_path(ledger, ".journal")
+    }
+
+    pub fn load(path: &Path) -> Result<Journal, String> {
+        let mut journal = Journal {
+            entries: Vec::new(),
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(journal),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let error = |e: String| format!("{}:{}: {}", path.display(), index + 1, e);
+            let line = line.map_err(|e| error(e.to_string()))?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (seq, time, action, change) = parse_line(&line).map_err(error)?;
+            match journal.entries.last_mut() {
+                Some(entry) if entry.seq == seq => entry.changes.push(change),
+                _ => journal.entries.push(Entry {
+                    seq,
+                    time,
+                    action,
+                    changes: vec![change],
+                }),
+            }
+        }
+        Ok(journal)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    fn entry(&self, seq: u64) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.seq == seq)
+    }
+
+    /// Replays the undo and redo markers, returning the sequence numbers that are currently
+    /// applied (most recent last) and those that were undone and can be redone.
+    pub fn stacks(&self) -> (Vec<u64>, Vec<u64>) {
+        let mut done = Vec::new();
+        let mut undone = Vec::new();
+        for entry in &self.entries {
+            match entry.action {
+                Action::Command(_) => {
+                    done.push(entry.seq);
+                    undone.clear();
+                }
+                Action::Undo(target) => {
+                    done.retain(|seq| *seq != target);
+                    undone.push(target);
+                }
+                Action::Redo(target) => {
+                    undone.retain(|seq| *seq != target);
+                    done.push(target);
+                }
+            }
+        }
+        (done, undone)
+    }
+
+    pub fn next_undo(&self) -> Option<&Entry> {
+        self.stacks().0.last().and_then(|seq| self.entry(*seq))
+    }
+
+    pub fn next_redo(&self) -> Option<&Entry> {
+        self.stacks().1.last().and_then(|seq| self.entry(*seq))
+    }
+
+    /// Appends an entry without reading the whole journal, returning its sequence number.
+    pub fn append(path: &Path, action: &Action, changes: &[Change]) -> Result<u64, String> {
+        let error = |e: io::Error| format!("{}: {}", path.display(), e);
+        let seq = last_seq(path).map_err(error)? + 1;
+        let time = Local::now().fixed_offset();
+        let target = match action {
+            Action::Command(_) => "-".to_string(),
+            Action::Undo(seq) | Action::Redo(seq) => seq.to_string(),
+        };
+        let name = match action {
+            Action::Command(name) => name.as_str(),
+            Action::Undo(_) => "undo",
+            Action::Redo(_) => "redo",
+        };
+
+        let mut contents = String::new();
+        if !path.exists() {
+            contents.push_str(HEADER);
+            contents.push('\n');
+        }
+        for change in changes {
+            let fields = [
+                seq.to_string(),
+                record::format_timestamp(time),
+                record::escape(name),
+                target.clone(),
+                encode_side(change.before.as_ref()),
+                encode_side(change.after.as_ref()),
+            ];
+            contents.push_str(&fields.join("\t"));
+            contents.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(error)?;
+        file.write_all(contents.as_bytes()).map_err(error)?;
+        file.sync_all().map_err(error)?;
+        Ok(seq)
+    }
+
+    /// Copies a ledger's journal to sit beside another ledger, e.g. after a migration.
+    pub fn copy(ledger: &Path, target_ledger: &Path) -> Result<(), String> {
+        let source = Self::path_for(ledger);
+        let target = Self::path_for(target_ledger);
+        if !source.exists() {
+            return Ok(());
+        }
+        fs::copy(&source, &target)
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", target.display(), e))
+    }
+}
+
+fn encode_side(expense: Option<&Expense>) -> String {
+    expense.map_or(String::new(), |e| record::escape(&record::encode(e)))
+}
+
+fn decode_side(field: &str) -> Result<Option<Expense>, String> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    record::decode(&record::unescape(field)?).map(Some)
+}
+
+fn parse_line(line: &str) -> Result<(u64, DateTime<FixedOffset>, Action, Change), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [seq, time, name, target, before, after] = fields[..] else {
+        return Err(format!(
+            "expected 6 tab-separated fields, found {}",
+            fields.len()
+        ));
+    };
+
+    let seq = seq
+        .parse::<u64>()
+        .map_err(|_| format!("invalid sequence number \"{}\"", seq))?;
+    let time = record::parse_timestamp(time)?;
+    let target = || {
+        target
+            .parse::<u64>()
+            .map_err(|_| format!("invalid target \"{}\"", target))
+    };
+    let action = match name {
+        "undo" => Action::Undo(target()?),
+        "redo" => Action::Redo(target()?),
+        _ => Action::Command(record::unescape(name)?),
+    };
+    let change = Change {
+        before: decode_side(before)?,
+        after: decode_side(after)?,
+    };
+    Ok((seq, time, action, change))
+}
+
+/// Reads the sequence number of the last entry by scanning back from the end of the file.
+fn last_seq(path: &Path) -> io::Result<u64> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut window = TAIL_WINDOW.min(len);
+    loop {
+        file.seek(SeekFrom::Start(len - window))?;
+        let mut tail = Vec::with_capacity(window as usize);
+        (&mut file).take(window).read_to_end(&mut tail)?;
+        let tail = String::from_utf8_lossy(&tail);
+        let mut lines = tail.lines().rev().filter(|line| !line.trim().is_empty());
+        // The first line of a partial window may be cut off, so only trust it when the
+        // window reaches the start of the file.
+        let candidate = lines.next();
+        let complete = window == len || lines.next().is_some();
+        if let Some(line) = candidate.filter(|_| complete) {
+            if line.starts_with('#') {
+                return Ok(0);
+            }
+            return Ok(line
+                .split('\t')
+                .next()
+                .and_then(|seq| seq.parse().ok())
+                .unwrap_or(0));
+        }
+        if window == len {
+            return Ok(0);
+        }
+        window = (window * 2).min(len);
+    }
+}
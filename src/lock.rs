@@ -0,0 +1,64 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// An advisory lock on a ledger or another file it depends on, held through a `.lock` file
+/// beside it so that it survives the file itself being replaced by an atomic rename.
+/// Released when dropped.
+#[derive(Debug)]
+pub struct LedgerLock {
+    _file: File,
+}
+
+impl LedgerLock {
+    pub fn path_for(ledger: &Path) -> PathBuf {
+        let mut name = ledger.as_os_str().to_owned();
+        name.push(".lock");
+        PathBuf::from(name)
+    }
+
+    /// Takes the lock, retrying until `timeout` has passed if another process holds it.
+    pub fn acquire(ledger: &Path, mode: LockMode, timeout: Duration) -> Result<LedgerLock, String> {
+        let path = Self::path_for(ledger);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let attempt = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+            match attempt {
+                Ok(()) => return Ok(LedgerLock { _file: file }),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(format!(
+                        "{} is locked by another invocation (waited {:.1}s; raise --lock-timeout to wait longer)",
+                        ledger.display(),
+                        timeout.as_secs_f64()
+                    ));
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(format!("{}: {}", path.display(), e));
+                }
+            }
+        }
+    }
+}
@@ -1,243 +1,2692 @@
-use chrono::Utc;
+mod atomic;
+mod categories;
+mod config;
+mod dates;
+mod import;
+mod journal;
+mod lock;
+mod money;
+mod output;
+mod query;
+mod rates;
+mod record;
+mod sqlite;
+mod storage;
+mod tags;
+
+use categories::{Categories, UNCATEGORIZED};
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate};
+use config::Config;
+use dates::{DateRange, Period, Timezone};
+use import::{ImportOptions, ImportProfile, Statement};
+use journal::{Action, Change, Journal};
+use lock::{LedgerLock, LockMode};
+use money::{Currency, Money};
+use output::{OutputFormat, Rows, note};
+use query::{Filter, FilterOptions, Query, QueryError, QueryOptions};
+use rates::{ExchangeRate, RateTable};
+use record::LoadError;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::fs::{File};
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
+use storage::{Backend, Storage};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct Expense {
     id: i32,
-    date: String,
+    date: NaiveDate,
     description: String,
-    amount: f32,
+    amount: Money,
+    category: String,
+    tags: BTreeSet<String>,
+    /// When the expense was moved to the trash.
+    deleted: Option<DateTime<FixedOffset>>,
+}
+
+impl Expense {
+    fn is_trashed(&self) -> bool {
+        self.deleted.is_some()
+    }
+
+    fn changes_from(&self, old: &Expense) -> Vec<(&'static str, String, String)> {
+        let mut changes = Vec::new();
+        if self.date != old.date {
+            changes.push(("date", old.date.to_string(), self.date.to_string()));
+        }
+        if self.description != old.description {
+            changes.push((
+                "description",
+                format!("\"{}\"", old.description),
+                format!("\"{}\"", self.description),
+            ));
+        }
+        if self.amount != old.amount {
+            changes.push((
+                "amount",
+                format!("{} {}", format_money(old.amount), old.amount.currency()),
+                format!("{} {}", format_money(self.amount), self.amount.currency()),
+            ));
+        }
+        if self.category != old.category {
+            changes.push(("category", old.category.clone(), self.category.clone()));
+        }
+        if self.tags != old.tags {
+            changes.push(("tags", join_tags(&old.tags), join_tags(&self.tags)));
+        }
+        changes
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExpenseEdit {
+    date: Option<NaiveDate>,
+    description: Option<String>,
+    amount: Option<String>,
+    currency: Option<Currency>,
+    category: Option<String>,
+    add_tags: Vec<String>,
+    remove_tags: Vec<String>,
+}
+
+/// One line of a summary breakdown.
+struct Group<'a> {
+    label: &'a str,
+    count: usize,
+    total: Money,
+    share: f64,
+}
+
+struct Settings {
+    config: Config,
+    file_name: PathBuf,
+    timezone: Timezone,
+    force: bool,
+    lock_timeout: Duration,
 }
 
 struct ExpenseTracker {
     expenses: Vec<Expense>,
     next_id: i32,
-    file_name: String,
+    file_name: PathBuf,
+    storage: Box<dyn Storage>,
+    /// Whether `expenses` holds the whole ledger rather than the result of a query, and so
+    /// may be saved back.
+    complete: bool,
+    dirty: bool,
+    /// What this invocation changed, for the journal.
+    changes: Vec<Change>,
+    action: Option<Action>,
+    diagnostics: Vec<LoadError>,
+    force: bool,
+    timezone: Timezone,
+    config: Config,
+    _lock: LedgerLock,
 }
 
 impl ExpenseTracker {
-    fn new() -> Self {
-        let mut tracker = ExpenseTracker {
+    fn new(settings: &Settings, file_name: PathBuf, lock: LedgerLock) -> Result<Self, String> {
+        let storage = Backend::detect(&file_name).open(&file_name)?;
+        Ok(ExpenseTracker {
             expenses: Vec::new(),
             next_id: 1,
-            file_name: "expenses.txt".to_string(),
-        };
-        tracker.load_expenses();
-        tracker
+            file_name,
+            storage,
+            complete: false,
+            dirty: false,
+            changes: Vec::new(),
+            action: None,
+            diagnostics: Vec::new(),
+            force: settings.force,
+            timezone: settings.timezone,
+            config: settings.config.clone(),
+            _lock: lock,
+        })
     }
 
-    fn get_current_date() -> String {
-        Utc::now().format("%Y-%m-%d").to_string()
+    fn load(&mut self) -> Result<(), String> {
+        let loaded = self.storage.load()?;
+        let quarantined = fs::read_to_string(self.quarantine_path()).unwrap_or_default();
+        let unreadable = loaded
+            .diagnostics
+            .iter()
+            .map(|d| d.content.as_str())
+            .chain(quarantined.lines().filter(|line| !line.starts_with('#')));
+        // IDs are never reused: the ledger records the next one, and older ledgers fall back
+        // to the highest ID seen, readable or not.
+        let highest = loaded
+            .expenses
+            .iter()
+            .map(|e| e.id)
+            .chain(unreadable.filter_map(record::leading_id))
+            .max();
+        self.next_id = highest
+            .map_or(1, |id| id + 1)
+            .max(loaded.next_id.unwrap_or(1));
+        self.expenses = loaded.expenses;
+        self.diagnostics = loaded.diagnostics;
+        self.complete = true;
+        Ok(())
     }
 
-    fn load_expenses(&mut self) {
-        if !Path::new(&self.file_name).exists() {
-            return;
+    /// Loads only the expenses a read-only command needs, letting the storage use its
+    /// indexes.
+    fn load_matching(&mut self, range: &DateRange, category: Option<&str>) -> Result<(), String> {
+        let loaded = self.storage.query(range, category)?;
+        self.expenses = loaded.expenses;
+        self.diagnostics = loaded.diagnostics;
+        self.complete = false;
+        Ok(())
+    }
+
+    /// Replaces the expense at `pos`, recording the change for the journal.
+    fn replace(&mut self, pos: usize, updated: Expense) {
+        self.changes.push(Change {
+            before: Some(self.expenses[pos].clone()),
+            after: Some(updated.clone()),
+        });
+        self.expenses[pos] = updated;
+        self.dirty = true;
+    }
+
+    fn quarantine_path(&self) -> PathBuf {
+        sibling_path(&self.file_name, ".quarantine")
+    }
+
+    fn rates_path(&self) -> PathBuf {
+        self.file_name.with_file_name("rates.txt")
+    }
+
+    fn categories_path(&self) -> PathBuf {
+        self.file_name.with_file_name("categories.txt")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        Journal::path_for(&self.file_name)
+    }
+
+    fn get_current_date(&self) -> NaiveDate {
+        self.timezone.today()
+    }
+
+    /// Appends every unreadable line to the quarantine file so the ledger can be rewritten
+    /// without losing them.
+    fn quarantine_bad_lines(&mut self) -> Result<usize, String> {
+        let path = self.quarantine_path();
+        let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(error)?;
+        for diagnostic in &self.diagnostics {
+            writeln!(file, "# {}", diagnostic).map_err(error)?;
+            writeln!(file, "{}", diagnostic.content).map_err(error)?;
         }
+        file.sync_all().map_err(error)?;
+        self.dirty = true;
+        Ok(std::mem::take(&mut self.diagnostics).len())
+    }
 
-        let file = File::open(&self.file_name).unwrap();
-        let reader = BufReader::new(file);
+    /// Writes the ledger back to disk if a command changed it, and records the changes in
+    /// the journal under `command` unless an undo or redo set its own action.
+    fn commit(&mut self, command: &str) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if !self.complete {
+            return Err("only part of the ledger was loaded".to_string());
+        }
+        self.save_expenses()?;
+        self.dirty = false;
 
-        for line in reader.lines().flatten() {
-            let parts: Vec<&str> = line.trim().splitn(3, ' ').collect();
-            if parts.len() < 3 {
-                continue;
-            }
+        if !self.changes.is_empty() {
+            let action = self
+                .action
+                .take()
+                .unwrap_or_else(|| Action::Command(command.to_string()));
+            Journal::append(&self.journal_path(), &action, &self.changes)
+                .map_err(|e| format!("the ledger was saved but the journal was not: {}", e))?;
+            self.changes.clear();
+        }
+        Ok(())
+    }
 
-            let id_date = parts[0..2].to_vec();
-            let rest = parts[2];
-            let desc_split: Vec<&str> = rest.rsplitn(2, '|').collect();
-            if desc_split.len() != 2 {
-                continue;
+    /// Moves an expense from the state `change.before` to `change.after`, refusing if it
+    /// no longer matches `before`.
+    fn apply(&mut self, change: &Change) -> Result<(), String> {
+        let id = change.id();
+        let pos = self.expenses.iter().position(|e| e.id == id);
+        if pos.map(|pos| &self.expenses[pos]) != change.before.as_ref() {
+            return Err(format!("expense {} was changed since", id));
+        }
+        match (pos, &change.after) {
+            (Some(pos), Some(after)) => self.expenses[pos] = after.clone(),
+            (Some(pos), None) => {
+                self.expenses.remove(pos);
             }
-
-            if let (Ok(id), Ok(amount)) = (id_date[0].parse::<i32>(), desc_split[0].parse::<f32>()) {
-                self.expenses.push(Expense {
-                    id,
-                    date: id_date[1].to_string(),
-                    description: desc_split[1].trim().to_string(),
-                    amount,
-                });
-                if id >= self.next_id {
-                    self.next_id = id + 1;
-                }
+            (None, Some(after)) => {
+                let pos = self.expenses.partition_point(|e| e.id < id);
+                self.expenses.insert(pos, after.clone());
+                self.next_id = self.next_id.max(id + 1);
             }
+            (None, None) => {}
+        }
+        self.changes.push(change.clone());
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<(), String> {
+        let journal = Journal::load(&self.journal_path())?;
+        let Some(entry) = journal.next_undo() else {
+            note!("# Nothing to undo");
+            return Ok(());
+        };
+        for change in entry.changes.iter().rev() {
+            self.apply(&change.inverse())
+                .map_err(|e| format!("cannot undo #{}: {}", entry.seq, e))?;
         }
+        self.action = Some(Action::Undo(entry.seq));
+        note!("# Undid #{} {}", entry.seq, describe_entry(entry));
+        Ok(())
     }
 
-    fn save_expenses(&self) {
-        let mut file = match File::create(&self.file_name) {
-            Ok(f) => f,
-            Err(_) => return,
+    fn redo(&mut self) -> Result<(), String> {
+        let journal = Journal::load(&self.journal_path())?;
+        let Some(entry) = journal.next_redo() else {
+            note!("# Nothing to redo");
+            return Ok(());
         };
+        for change in &entry.changes {
+            self.apply(change)
+                .map_err(|e| format!("cannot redo #{}: {}", entry.seq, e))?;
+        }
+        self.action = Some(Action::Redo(entry.seq));
+        note!("# Redid #{} {}", entry.seq, describe_entry(entry));
+        Ok(())
+    }
+
+    fn history(&self, limit: usize) -> Result<(), String> {
+        let journal = Journal::load(&self.journal_path())?;
+        let (_, undone) = journal.stacks();
+        let entries = journal.entries();
+        let entries = &entries[entries.len().saturating_sub(limit)..];
+
+        if !output::current().is_table() {
+            let mut rows = Rows::new(["seq", "time", "action", "target", "changes", "undone"]);
+            for entry in entries {
+                let (action, target) = match &entry.action {
+                    Action::Command(name) => (name.as_str(), None),
+                    Action::Undo(seq) => ("undo", Some(*seq)),
+                    Action::Redo(seq) => ("redo", Some(*seq)),
+                };
+                rows.push(vec![
+                    json!(entry.seq),
+                    json!(record::format_timestamp(entry.time)),
+                    json!(action),
+                    json!(target),
+                    json!(describe_entry(entry)),
+                    json!(undone.contains(&entry.seq)),
+                ]);
+            }
+            emit(&rows, false);
+            return Ok(());
+        }
+
+        if entries.is_empty() {
+            note!("# No history recorded yet.");
+            return Ok(());
+        }
+        println!("# {:>6}  {:<17}{:<9}Changes", "Seq", "Time", "Action");
+        for entry in entries {
+            let action = match &entry.action {
+                Action::Command(name) => name.clone(),
+                Action::Undo(seq) => format!("undo #{}", seq),
+                Action::Redo(seq) => format!("redo #{}", seq),
+            };
+            let note = if undone.contains(&entry.seq) {
+                " (undone)"
+            } else {
+                ""
+            };
+            println!(
+                "# {:>6}  {:<17}{:<9}{}{}",
+                entry.seq,
+                self.timezone
+                    .local_time(entry.time)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                action,
+                describe_entry(entry),
+                note
+            );
+        }
+        Ok(())
+    }
 
-        for expense in &self.expenses {
-            let _ = writeln!(
-                file,
-                "{} {} {}|{}",
-                expense.id, expense.date, expense.description, expense.amount
+    fn save_expenses(&mut self) -> Result<(), String> {
+        if !self.diagnostics.is_empty() {
+            if !self.force {
+                return Err(format!(
+                    "refusing to overwrite {} while it has {} unreadable line(s)",
+                    self.file_name.display(),
+                    self.diagnostics.len()
+                ));
+            }
+            let count = self.quarantine_bad_lines()?;
+            note!(
+                "# Moved {} unreadable line(s) to {}",
+                count,
+                self.quarantine_path().display()
             );
         }
+
+        self.storage.save(&self.expenses, self.next_id)
+    }
+
+    fn add_expense(
+        &mut self,
+        date: NaiveDate,
+        description: String,
+        amount: Money,
+        category: String,
+        tags: BTreeSet<String>,
+    ) {
+        let expense = self.insert_expense(date, description, amount, category, tags);
+
+        note!(
+            "# Expense added successfully (ID: {})",
+            expense.id
+        );
     }
 
-    fn add_expense(&mut self, description: String, amount: f32) {
+    fn insert_expense(
+        &mut self,
+        date: NaiveDate,
+        description: String,
+        amount: Money,
+        category: String,
+        tags: BTreeSet<String>,
+    ) -> Expense {
         let expense = Expense {
             id: self.next_id,
-            date: Self::get_current_date(),
+            date,
             description,
             amount,
+            category,
+            tags,
+            deleted: None,
         };
         self.expenses.push(expense.clone());
         self.next_id += 1;
+        self.dirty = true;
+        self.changes.push(Change {
+            before: None,
+            after: Some(expense.clone()),
+        });
+        expense
+    }
 
-        println!(
-            "# Expense added successfully (ID: {})",
-            expense.id
+    /// Adds the rows of a bank statement as expenses, leaving out those that duplicate an
+    /// existing expense unless `keep_duplicates` is set. Without `confirmed` it only shows
+    /// what would be imported.
+    fn import_statement(
+        &mut self,
+        statement: Statement,
+        category: &str,
+        keep_duplicates: bool,
+        confirmed: bool,
+    ) {
+        let duplicates = import::find_duplicates(&statement.rows, &self.expenses);
+        let skipped = |duplicate: &Option<i32>| duplicate.is_some() && !keep_duplicates;
+        let dropped = duplicates.iter().filter(|d| skipped(d)).count();
+        let count = duplicates.len() - dropped;
+
+        if !confirmed {
+            self.print_statement(&statement, &duplicates, category);
+            note!(
+                "# {} expense(s) would be imported, skipping {} duplicate(s) and {} credit(s). \
+                 Re-run with --yes to apply.",
+                count,
+                dropped,
+                statement.skipped
+            );
+            return;
+        }
+        for (row, duplicate) in statement.rows.into_iter().zip(&duplicates) {
+            if !skipped(duplicate) {
+                self.insert_expense(
+                    row.date,
+                    row.description,
+                    row.amount,
+                    category.to_string(),
+                    BTreeSet::new(),
+                );
+            }
+        }
+        note!(
+            "# Imported {} expense(s); skipped {} duplicate(s) and {} credit(s)",
+            count,
+            dropped,
+            statement.skipped
         );
     }
 
-    fn list_expenses(&self) {
-        if self.expenses.is_empty() {
-            println!("# No expenses to display.");
+    fn list_expenses(&self, query: &Query) {
+        self.print_expenses(&query.run(&self.expenses));
+    }
+
+    fn print_expenses(&self, expenses: &[&Expense]) {
+        let date_format = self.config.date_format();
+
+        if !output::current().is_table() {
+            emit(&self.expense_rows(expenses), false);
+            return;
+        }
+
+        if expenses.is_empty() {
+            note!("# No expenses to display.");
             return;
         }
 
+        let width = |column| self.config.column_width(column);
         println!(
-            "# {:>6}{:>12}{:>18}{:>14}",
-            "ID", "Date", "Description", "Amount"
+            "# {:>w0$}{:>w1$}{:>w2$}{:>w3$}{:>w4$}{:>w5$}  Tags",
+            "ID",
+            "Date",
+            "Description",
+            "Amount",
+            "Cur",
+            "Category",
+            w0 = width("id"),
+            w1 = width("date"),
+            w2 = width("description"),
+            w3 = width("amount"),
+            w4 = width("currency"),
+            w5 = width("category"),
         );
-        for e in &self.expenses {
-            println!(
-                "# {:>6}{:>12}{:>18}${:>12.2}",
-                e.id, e.date, e.description, e.amount
+        for e in expenses {
+            let line = format!(
+                "# {:>w0$}{:>w1$}{:>w2$}{:>w3$}{:>w4$}{:>w5$}  {}",
+                e.id,
+                e.date.format(date_format).to_string(),
+                e.description,
+                format_money(e.amount),
+                e.amount.currency(),
+                e.category,
+                join_tags(&e.tags),
+                w0 = width("id"),
+                w1 = width("date"),
+                w2 = width("description"),
+                w3 = width("amount"),
+                w4 = width("currency"),
+                w5 = width("category"),
             );
+            println!("{}", line.trim_end());
         }
     }
 
-    fn sum_expenses(&self, month: Option<u32>) -> f32 {
-        let mut total = 0.0;
-        for e in &self.expenses {
-            let expense_month = e
-                .date
-                .get(5..7)
-                .and_then(|s| s.parse::<u32>().ok())
-                .unwrap_or(0);
+    /// Previews an import: each statement row with the expense it duplicates, if any.
+    fn print_statement(&self, statement: &Statement, duplicates: &[Option<i32>], category: &str) {
+        let date_format = self.config.date_format();
 
-            if month.is_none() || month.unwrap() == expense_month {
-                total += e.amount;
+        if !output::current().is_table() {
+            let mut rows = Rows::new([
+                "line",
+                "date",
+                "description",
+                "amount",
+                "currency",
+                "category",
+                "duplicate_of",
+            ]);
+            for (row, duplicate) in statement.rows.iter().zip(duplicates) {
+                rows.push(vec![
+                    json!(row.line),
+                    json!(row.date.format(date_format).to_string()),
+                    json!(row.description),
+                    json!(row.amount.to_string()),
+                    json!(row.amount.currency().to_string()),
+                    json!(category),
+                    json!(duplicate),
+                ]);
             }
+            emit(&rows, false);
+            return;
+        }
+
+        if statement.rows.is_empty() {
+            note!("# No expenses to import.");
+            return;
         }
 
-        if let Some(m) = month {
+        let width = |column| self.config.column_width(column);
+        println!(
+            "# {:>w0$}{:>w1$}{:>w2$}{:>w3$}{:>w4$}  Status",
+            "Line",
+            "Date",
+            "Description",
+            "Amount",
+            "Cur",
+            w0 = width("id"),
+            w1 = width("date"),
+            w2 = width("description"),
+            w3 = width("amount"),
+            w4 = width("currency"),
+        );
+        for (row, duplicate) in statement.rows.iter().zip(duplicates) {
             println!(
-                "# Total expenses for month {}: ${:.2}",
-                m, total
+                "# {:>w0$}{:>w1$}{:>w2$}{:>w3$}{:>w4$}  {}",
+                row.line,
+                row.date.format(date_format).to_string(),
+                row.description,
+                format_money(row.amount),
+                row.amount.currency(),
+                match duplicate {
+                    Some(id) => format!("duplicate of {}", id),
+                    None => "new".to_string(),
+                },
+                w0 = width("id"),
+                w1 = width("date"),
+                w2 = width("description"),
+                w3 = width("amount"),
+                w4 = width("currency"),
             );
-        } else {
-            println!("# Total expenses: ${:.2}", total);
         }
+    }
 
-        total
+    /// Expenses as records for the machine-readable formats, with amounts as exact
+    /// decimal strings.
+    fn expense_rows(&self, expenses: &[&Expense]) -> Rows {
+        let mut rows = Rows::new(EXPENSE_COLUMNS);
+        for e in expenses {
+            rows.push(self.expense_values(e));
+        }
+        rows
     }
 
-    fn delete_expense(&mut self, id: i32) {
-        if let Some(pos) = self.expenses.iter().position(|x| x.id == id) {
-            self.expenses.remove(pos);
-            println!("# Expense deleted successfully");
-        } else {
-            println!("# ERROR: Expense with ID {} not found.", id);
+    fn expense_values(&self, e: &Expense) -> Vec<Value> {
+        vec![
+            json!(e.id),
+            json!(e.date.format(self.config.date_format()).to_string()),
+            json!(e.description),
+            json!(e.amount.to_string()),
+            json!(e.amount.currency().to_string()),
+            json!(e.category),
+            json!(e.tags),
+            json!(e.deleted.map(record::format_timestamp)),
+        ]
+    }
+
+    /// The expenses a command changed, each with what happened to it.
+    fn change_rows(&self, changes: &[Change]) -> Rows {
+        let mut rows = Rows::new(["change"].into_iter().chain(EXPENSE_COLUMNS));
+        for change in changes {
+            if let Some(e) = change.after.as_ref().or(change.before.as_ref()) {
+                let mut values = vec![json!(change_kind(change))];
+                values.extend(self.expense_values(e));
+                rows.push(values);
+            }
         }
+        rows
     }
-}
 
-impl Drop for ExpenseTracker {
-    fn drop(&mut self) {
-        self.save_expenses();
+    fn converted_amounts(
+        &self,
+        filter: &Filter,
+        base: Option<(Currency, &RateTable)>,
+    ) -> Result<Vec<(&Expense, Money)>, String> {
+        let mut amounts: Vec<(&Expense, Money)> = Vec::new();
+        for e in self.expenses.iter().filter(|e| filter.matches(e)) {
+            let amount = match base {
+                Some((currency, rates)) => rates
+                    .convert(e.amount, currency, e.date)
+                    .map_err(|err| format!("expense {}: {}", e.id, err))?,
+                None => e.amount,
+            };
+            amounts.push((e, amount));
+        }
+        Ok(amounts)
     }
-}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+    fn sum_expenses(
+        &self,
+        filter: &Filter,
+        base: Option<(Currency, &RateTable)>,
+        by_category: bool,
+    ) -> Result<Vec<Money>, String> {
+        let amounts = self.converted_amounts(filter, base)?;
+        Self::print_summary(
+            &amounts,
+            &filter.range,
+            base.map(|(c, _)| c),
+            by_category,
+            None,
+        )
+    }
 
-    if args.len() < 2 {
-        eprintln!("ERROR 0x00: Insufficient Arguments.");
-        process::exit(1);
+    /// Sums several ledgers together, with a breakdown of how much each contributed.
+    fn sum_ledgers(
+        ledgers: &[(String, ExpenseTracker)],
+        filter: &Filter,
+        base: Option<(Currency, &RateTable)>,
+        by_category: bool,
+    ) -> Result<Vec<Money>, String> {
+        let mut amounts = Vec::new();
+        let mut by_ledger = Vec::new();
+        for (name, tracker) in ledgers {
+            let ledger_amounts = tracker
+                .converted_amounts(filter, base)
+                .map_err(|err| format!("ledger \"{}\": {}", name, err))?;
+            by_ledger.extend(ledger_amounts.iter().map(|(_, a)| (name.as_str(), *a)));
+            amounts.extend(ledger_amounts);
+        }
+        Self::print_summary(
+            &amounts,
+            &filter.range,
+            base.map(|(c, _)| c),
+            by_category,
+            Some(&by_ledger),
+        )
     }
 
-    let command = &args[1];
-    let mut tracker = ExpenseTracker::new();
+    fn print_summary(
+        amounts: &[(&Expense, Money)],
+        range: &DateRange,
+        base: Option<Currency>,
+        by_category: bool,
+        by_ledger: Option<&[(&str, Money)]>,
+    ) -> Result<Vec<Money>, String> {
+        let mut totals: BTreeMap<Currency, Money> = BTreeMap::new();
+        for (_, amount) in amounts {
+            let total = totals
+                .entry(amount.currency())
+                .or_insert_with(|| Money::zero(amount.currency()));
+            *total = total
+                .checked_add(*amount)
+                .ok_or_else(|| too_large(amount.currency()))?;
+        }
 
-    match command.as_str() {
-        "add" => {
-            let mut description = String::new();
-            let mut amount = 0.0;
+        if totals.is_empty() {
+            let currency = base.unwrap_or_default();
+            totals.insert(currency, Money::zero(currency));
+        }
 
-            let mut i = 2;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--description" if i + 1 < args.len() => {
-                        description = args[i + 1].clone();
-                        i += 1;
-                    }
-                    "--amount" if i + 1 < args.len() => {
-                        amount = args[i + 1].parse().unwrap_or(0.0);
-                        i += 1;
-                    }
-                    _ => {}
+        let mut breakdowns = Vec::new();
+        if let Some(by_ledger) = by_ledger {
+            breakdowns.push(("Ledger", Self::breakdown(by_ledger, &totals)?));
+        }
+        if by_category {
+            let by_category: Vec<(&str, Money)> = amounts
+                .iter()
+                .map(|(e, amount)| (e.category.as_str(), *amount))
+                .collect();
+            breakdowns.push(("Category", Self::breakdown(&by_category, &totals)?));
+        }
+
+        if !output::current().is_table() {
+            let mut rows = Rows::new(["group", "name", "currency", "count", "total", "share"]);
+            for (heading, groups) in &breakdowns {
+                for group in groups {
+                    rows.push(vec![
+                        json!(heading.to_lowercase()),
+                        json!(group.label),
+                        json!(group.total.currency().to_string()),
+                        json!(group.count),
+                        json!(group.total.to_string()),
+                        json!((group.share * 10.0).round() / 10.0),
+                    ]);
                 }
-                i += 1;
             }
+            for total in totals.values() {
+                let count = amounts
+                    .iter()
+                    .filter(|(_, amount)| amount.currency() == total.currency())
+                    .count();
+                rows.push(vec![
+                    json!("total"),
+                    Value::Null,
+                    json!(total.currency().to_string()),
+                    json!(count),
+                    json!(total.to_string()),
+                    json!(100.0),
+                ]);
+            }
+            emit(&rows, false);
+            return Ok(totals.into_values().collect());
+        }
 
-            if description.is_empty() || amount <= 0.0 {
-                eprintln!("ERROR 0x01: Invalid arguments for adding an expense.");
-                process::exit(1);
+        for (heading, groups) in &breakdowns {
+            Self::print_breakdown(heading, groups);
+        }
+        let label = if range.is_unbounded() {
+            "Total expenses".to_string()
+        } else {
+            format!("Total expenses {}", range)
+        };
+        let show_code = base.is_some() || totals.len() > 1;
+        for total in totals.values() {
+            if show_code {
+                println!(
+                    "# {} ({}): {}{}",
+                    label,
+                    total.currency(),
+                    total.currency().symbol(),
+                    total
+                );
+            } else {
+                println!("# {}: {}{}", label, total.currency().symbol(), total);
             }
+        }
 
-            tracker.add_expense(description, amount);
+        Ok(totals.into_values().collect())
+    }
+
+    /// Count, total and share of the grand total for each group label, per currency.
+    fn breakdown<'a>(
+        amounts: &[(&'a str, Money)],
+        totals: &BTreeMap<Currency, Money>,
+    ) -> Result<Vec<Group<'a>>, String> {
+        let mut groups: BTreeMap<(Currency, &str), (usize, Money)> = BTreeMap::new();
+        for (label, amount) in amounts {
+            let entry = groups
+                .entry((amount.currency(), label))
+                .or_insert_with(|| (0, Money::zero(amount.currency())));
+            entry.0 += 1;
+            entry.1 = entry
+                .1
+                .checked_add(*amount)
+                .ok_or_else(|| too_large(amount.currency()))?;
         }
-        "list" => {
-            tracker.list_expenses();
+
+        let mut rows: Vec<Group> = groups
+            .into_iter()
+            .map(|((currency, label), (count, total))| {
+                let grand_total = totals[&currency].minor();
+                let share = if grand_total == 0 {
+                    0.0
+                } else {
+                    total.minor() as f64 * 100.0 / grand_total as f64
+                };
+                Group {
+                    label,
+                    count,
+                    total,
+                    share,
+                }
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            a.total
+                .currency()
+                .cmp(&b.total.currency())
+                .then(b.total.minor().cmp(&a.total.minor()))
+                .then(a.label.cmp(b.label))
+        });
+        Ok(rows)
+    }
+
+    fn print_breakdown(heading: &str, groups: &[Group]) {
+        println!(
+            "# {:>16}{:>8}{:>14}{:>5}{:>9}",
+            heading, "Count", "Total", "Cur", "Share"
+        );
+        for group in groups {
+            println!(
+                "# {:>16}{:>8}{:>14}{:>5}{:>8.1}%",
+                group.label,
+                group.count,
+                format_money(group.total),
+                group.total.currency(),
+                group.share
+            );
         }
-        "summary" => {
-            let mut month: Option<u32> = None;
+    }
 
-            let mut i = 2;
-            while i < args.len() {
-                if args[i] == "--month" && i + 1 < args.len() {
-                    month = args[i + 1].parse().ok();
-                    i += 1;
+    fn report_expenses(
+        &self,
+        filter: &Filter,
+        period: Period,
+        base: Option<(Currency, &RateTable)>,
+        by_category: bool,
+    ) -> Result<(), String> {
+        let amounts = self.converted_amounts(filter, base)?;
+        let range = &filter.range;
+        let currency = match base {
+            Some((currency, _)) => currency,
+            None => {
+                let currencies: BTreeSet<Currency> =
+                    amounts.iter().map(|(_, a)| a.currency()).collect();
+                if currencies.len() > 1 {
+                    return Err(
+                        "expenses use several currencies, choose one with --base".to_string()
+                    );
                 }
-                i += 1;
+                currencies.into_iter().next().unwrap_or_default()
             }
+        };
+
+        let from = range.from.or(amounts.iter().map(|(e, _)| e.date).min());
+        let to = range.to.or(amounts.iter().map(|(e, _)| e.date).max());
+        let (Some(from), Some(to)) = (from, to) else {
+            note!("# No expenses to report.");
+            emit(&Rows::new(["row", "currency", "total"]), false);
+            return Ok(());
+        };
 
-            tracker.sum_expenses(month);
+        let starts = period.starts_between(from, to);
+        let mut totals = vec![0; starts.len()];
+        let mut rows: BTreeMap<&str, Vec<i64>> = BTreeMap::new();
+        for (e, amount) in &amounts {
+            let column = starts.partition_point(|start| *start <= e.date) - 1;
+            totals[column] = add_minor(totals[column], *amount)?;
+            if by_category {
+                let row = rows
+                    .entry(e.category.as_str())
+                    .or_insert_with(|| vec![0; starts.len()]);
+                row[column] = add_minor(row[column], *amount)?;
+            }
         }
-        "delete" => {
-            let mut id = 0;
+        // Every cell is non-negative, so once the grand total fits so does every other sum.
+        totals
+            .iter()
+            .try_fold(0_i64, |sum, value| sum.checked_add(*value))
+            .ok_or_else(|| too_large(currency))?;
 
-            let mut i = 2;
-            while i < args.len() {
-                if args[i] == "--id" && i + 1 < args.len() {
-                    id = args[i + 1].parse().unwrap_or(0);
-                    i += 1;
-                }
-                i += 1;
+        let labels: Vec<String> = starts.iter().map(|s| period.label(*s)).collect();
+        if !output::current().is_table() {
+            let columns = ["row", "currency"].into_iter().map(String::from);
+            let mut records = Rows::new(columns.chain(labels).chain(["total".to_string()]));
+            let named = rows.iter().map(|(category, values)| (*category, values));
+            for (label, values) in named.chain([("total", &totals)]) {
+                let mut record = vec![json!(label), json!(currency.to_string())];
+                let total: i64 = values.iter().sum();
+                let amounts = values.iter().chain([&total]);
+                record.extend(amounts.map(|v| json!(Money::from_minor(*v, currency).to_string())));
+                records.push(record);
             }
+            emit(&records, false);
+            return Ok(());
+        }
 
-            if id <= 0 {
-                eprintln!("ERROR 0x02: Invalid ID for deletion.");
-                process::exit(1);
+        let money = |minor: i64| format_money(Money::from_minor(minor, currency));
+        let mut header = labels;
+        header.push("Total".to_string());
+
+        let mut table: Vec<(String, Vec<String>)> = Vec::new();
+        for (category, values) in &rows {
+            let mut cells: Vec<String> = values.iter().map(|v| money(*v)).collect();
+            cells.push(money(values.iter().sum()));
+            table.push((category.to_string(), cells));
+        }
+        let mut total_cells: Vec<String> = totals.iter().map(|v| money(*v)).collect();
+        total_cells.push(money(totals.iter().sum()));
+        table.push(("Total".to_string(), total_cells));
+
+        let mut change_cells = vec!["-".to_string()];
+        for pair in totals.windows(2) {
+            change_cells.push(if pair[0] == 0 {
+                "n/a".to_string()
+            } else {
+                format!(
+                    "{:+.1}%",
+                    (pair[1] - pair[0]) as f64 * 100.0 / pair[0] as f64
+                )
+            });
+        }
+        change_cells.push(String::new());
+        table.push(("Change".to_string(), change_cells));
+
+        let label_width = table
+            .iter()
+            .map(|(l, _)| l.len())
+            .max()
+            .unwrap_or(0)
+            .max(16);
+        let width = table
+            .iter()
+            .flat_map(|(_, cells)| cells.iter())
+            .chain(header.iter())
+            .map(|cell| cell.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+
+        println!(
+            "# Expenses {} by {} ({})",
+            DateRange::between(from, to),
+            period.name(),
+            currency
+        );
+        let mut line = format!(
+            "# {:>label_width$}",
+            if by_category { "Category" } else { "" }
+        );
+        for cell in &header {
+            line.push_str(&format!("{:>width$}", cell));
+        }
+        println!("{}", line);
+        for (label, cells) in table {
+            let mut line = format!("# {:>label_width$}", label);
+            for cell in cells {
+                line.push_str(&format!("{:>width$}", cell));
             }
+            println!("{}", line.trim_end());
+        }
+        Ok(())
+    }
 
-            tracker.delete_expense(id);
+    fn edit_expense(&mut self, id: i32, edit: ExpenseEdit) -> Result<(), String> {
+        let Some(pos) = self.expenses.iter().position(|x| x.id == id) else {
+            note!("# ERROR: Expense with ID {} not found.", id);
+            return Ok(());
+        };
+        if self.expenses[pos].is_trashed() {
+            note!("# ERROR: Expense {} is in the trash; restore it first.", id);
+            return Ok(());
         }
-        _ => {
-            eprintln!("ERROR 0x03: Unknown command.");
+
+        let old = &self.expenses[pos];
+        let mut updated = old.clone();
+        if let Some(date) = edit.date {
+            updated.date = date;
+        }
+        if let Some(description) = edit.description {
+            updated.description = description;
+        }
+        if edit.amount.is_some() || edit.currency.is_some() {
+            let currency = edit.currency.unwrap_or(old.amount.currency());
+            let amount = edit.amount.unwrap_or_else(|| old.amount.to_string());
+            updated.amount = Money::parse(&amount, currency).map_err(|e| e.to_string())?;
+            if !updated.amount.is_positive() {
+                return Err("amount must be greater than zero".to_string());
+            }
+        }
+        if let Some(category) = edit.category {
+            updated.category = category;
+        }
+        for tag in edit.remove_tags {
+            updated.tags.remove(&tag);
+        }
+        updated.tags.extend(edit.add_tags);
+
+        let changes = updated.changes_from(old);
+        if changes.is_empty() {
+            note!("# No changes made to expense {}", id);
+            return Ok(());
+        }
+
+        self.replace(pos, updated);
+        note!("# Expense {} updated successfully", id);
+        for (field, before, after) in changes {
+            note!("#   {}: {} -> {}", field, before, after);
+        }
+        Ok(())
+    }
+
+    /// Moves an expense to the trash, where it is kept until purged.
+    fn delete_expense(&mut self, id: i32) {
+        let Some(pos) = self.expenses.iter().position(|x| x.id == id) else {
+            note!("# ERROR: Expense with ID {} not found.", id);
+            return;
+        };
+        if self.expenses[pos].is_trashed() {
+            note!("# ERROR: Expense {} is already in the trash.", id);
+            return;
+        }
+
+        let mut trashed = self.expenses[pos].clone();
+        trashed.deleted = Some(Local::now().fixed_offset());
+        self.replace(pos, trashed);
+        note!(
+            "# Expense {} moved to the trash (restore it with `restore --id {}`)",
+            id,
+            id
+        );
+    }
+
+    fn restore_expense(&mut self, id: i32) {
+        let Some(pos) = self
+            .expenses
+            .iter()
+            .position(|x| x.id == id && x.is_trashed())
+        else {
+            note!("# ERROR: No expense with ID {} in the trash.", id);
+            return;
+        };
+
+        let mut restored = self.expenses[pos].clone();
+        restored.deleted = None;
+        self.replace(pos, restored);
+        note!("# Expense {} restored", id);
+    }
+
+    /// Applies `update` to every expense matching `filter` that it would change, or only
+    /// previews them unless `confirmed`. `outcome` describes what happens to them.
+    fn bulk_update(
+        &mut self,
+        filter: &Filter,
+        confirmed: bool,
+        outcome: &str,
+        update: impl Fn(&mut Expense),
+    ) {
+        let mut affected = Vec::new();
+        for (pos, e) in self.expenses.iter().enumerate() {
+            if !filter.matches(e) {
+                continue;
+            }
+            let mut updated = e.clone();
+            update(&mut updated);
+            if updated != *e {
+                affected.push((pos, updated));
+            }
+        }
+        if affected.is_empty() {
+            note!("# No expenses match.");
+            if !confirmed {
+                emit(&self.expense_rows(&[]), false);
+            }
+            return;
+        }
+
+        if !confirmed {
+            let preview: Vec<&Expense> = affected
+                .iter()
+                .map(|(pos, _)| &self.expenses[*pos])
+                .collect();
+            self.print_expenses(&preview);
+            note!(
+                "# {} expense(s) would be {}. Re-run with --yes to apply.",
+                affected.len(),
+                outcome
+            );
+            return;
+        }
+        let count = affected.len();
+        for (pos, updated) in affected {
+            self.replace(pos, updated);
+        }
+        note!("# {} expense(s) {}", count, outcome);
+    }
+
+    fn list_trash(&self) {
+        let trashed: Vec<&Expense> = self.expenses.iter().filter(|e| e.is_trashed()).collect();
+        if !output::current().is_table() {
+            emit(&self.expense_rows(&trashed), false);
+            return;
+        }
+        if trashed.is_empty() {
+            note!("# The trash is empty.");
+            return;
+        }
+
+        let date_format = self.config.date_format();
+        let width = |column| self.config.column_width(column);
+        println!(
+            "# {:>w0$}{:>18}{:>w1$}{:>w2$}{:>w3$}{:>w4$}",
+            "ID",
+            "Deleted",
+            "Date",
+            "Description",
+            "Amount",
+            "Cur",
+            w0 = width("id"),
+            w1 = width("date"),
+            w2 = width("description"),
+            w3 = width("amount"),
+            w4 = width("currency"),
+        );
+        for e in trashed {
+            let deleted = e.deleted.map_or(String::new(), |time| {
+                self.timezone
+                    .local_time(time)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            });
+            println!(
+                "# {:>w0$}{:>18}{:>w1$}{:>w2$}{:>w3$}{:>w4$}",
+                e.id,
+                deleted,
+                e.date.format(date_format).to_string(),
+                e.description,
+                format_money(e.amount),
+                e.amount.currency(),
+                w0 = width("id"),
+                w1 = width("date"),
+                w2 = width("description"),
+                w3 = width("amount"),
+                w4 = width("currency"),
+            );
+        }
+    }
+
+    /// Removes for good the expenses that were moved to the trash on or before `cutoff`.
+    fn purge_trash(&mut self, cutoff: NaiveDate) {
+        let (purged, kept): (Vec<Expense>, Vec<Expense>) = std::mem::take(&mut self.expenses)
+            .into_iter()
+            .partition(|e| {
+                e.deleted
+                    .is_some_and(|time| self.timezone.local_time(time).date() <= cutoff)
+            });
+        self.expenses = kept;
+        if purged.is_empty() {
+            note!("# Nothing in the trash was deleted on or before {}", cutoff);
+            return;
+        }
+
+        note!("# Purged {} expense(s) from the trash", purged.len());
+        self.changes.extend(purged.into_iter().map(|e| Change {
+            before: Some(e),
+            after: None,
+        }));
+        self.dirty = true;
+    }
+}
+
+const EXPENSE_COLUMNS: [&str; 8] = [
+    "id",
+    "date",
+    "description",
+    "amount",
+    "currency",
+    "category",
+    "tags",
+    "deleted",
+];
+
+const MUTATING_COMMANDS: &[&str] = &[
+    "add", "edit", "update", "delete", "restore", "migrate", "undo", "redo",
+];
+const DEFAULT_LOCK_TIMEOUT_SECS: f64 = 10.0;
+
+fn parse_settings(args: &mut Vec<String>) -> Settings {
+    let config = match Config::default_path().as_deref().map(Config::load) {
+        Some(Ok(config)) => config,
+        Some(Err(err)) => {
+            eprintln!("ERROR 0x13: Cannot read config: {}.", err);
+            process::exit(1);
+        }
+        None => Config::default(),
+    };
+
+    let timezone = take_option(args, "--tz")
+        .or_else(|| env::var("EXPENSES_TZ").ok())
+        .or_else(|| config.timezone.clone());
+    let timezone = match timezone.as_deref().map(Timezone::parse) {
+        Some(Ok(timezone)) => timezone,
+        Some(Err(err)) => {
+            eprintln!("ERROR 0x0D: Invalid timezone: {}.", err);
+            process::exit(1);
+        }
+        None => Timezone::Local,
+    };
+
+    let format = match take_option(args, "--output").map(|name| OutputFormat::parse(&name)) {
+        Some(Ok(format)) => format,
+        Some(Err(err)) => {
+            eprintln!("ERROR 0x14: Invalid --output: {}.", err);
+            process::exit(1);
+        }
+        None => config.output(),
+    };
+    output::init(format);
+
+    let lock_timeout = match take_option(args, "--lock-timeout").map(|secs| secs.parse::<f64>()) {
+        Some(Ok(secs)) if secs.is_finite() && secs >= 0.0 => secs,
+        Some(_) => {
+            eprintln!("ERROR 0x12: Invalid --lock-timeout, expected a number of seconds.");
             process::exit(1);
         }
+        None => DEFAULT_LOCK_TIMEOUT_SECS,
+    };
+
+    let ledger = take_option(args, "--ledger");
+    let file_name = take_option(args, "--file");
+    if file_name.is_some() && ledger.is_some() {
+        eprintln!("ERROR 0x15: Choose either --file or --ledger.");
+        process::exit(1);
+    }
+    let ledger = ledger.map(|name| match config.ledger(&name) {
+        Some(path) => path,
+        None => {
+            eprintln!("ERROR 0x15: {}.", config::unknown_ledger(&name));
+            process::exit(1);
+        }
+    });
+
+    let file_name = file_name
+        .map(PathBuf::from)
+        .or(ledger)
+        .or_else(|| {
+            env::var_os("EXPENSES_FILE")
+                .filter(|file| !file.is_empty())
+                .map(PathBuf::from)
+        })
+        .or_else(|| config.data_file())
+        .unwrap_or_else(|| {
+            let default = config::default_data_file()
+                .unwrap_or_else(|| PathBuf::from(config::DEFAULT_DATA_FILE));
+            let legacy = Path::new(config::DEFAULT_DATA_FILE);
+            if !default.exists() && legacy.exists() && default != legacy {
+                eprintln!(
+                    "WARNING: Using {}; the {} in this directory is no longer read by default. \
+                     Pass --file {} or move it there to keep using it.",
+                    default.display(),
+                    config::DEFAULT_DATA_FILE,
+                    config::DEFAULT_DATA_FILE
+                );
+            }
+            default
+        });
+
+    Settings {
+        config,
+        file_name,
+        timezone,
+        force: take_flag(args, "--force"),
+        lock_timeout: Duration::from_secs_f64(lock_timeout),
+    }
+}
+
+/// Locks and loads a ledger, exiting with the matching error if either step fails.
+fn open_tracker(settings: &Settings, file_name: &Path, mode: LockMode) -> ExpenseTracker {
+    if let Some(dir) = file_name.parent()
+        && !dir.as_os_str().is_empty()
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        eprintln!(
+            "ERROR 0x0E: Cannot load expenses: {}: {}.",
+            dir.display(),
+            err
+        );
+        process::exit(1);
+    }
+    let lock = match LedgerLock::acquire(file_name, mode, settings.lock_timeout) {
+        Ok(lock) => lock,
+        Err(err) => {
+            eprintln!("ERROR 0x12: Cannot lock expenses: {}.", err);
+            process::exit(1);
+        }
+    };
+    match ExpenseTracker::new(settings, file_name.to_path_buf(), lock) {
+        Ok(tracker) => tracker,
+        Err(err) => {
+            eprintln!("ERROR 0x0E: Cannot load expenses: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Opens the ledgers named in a comma-separated list, or every ledger for "all".
+fn open_ledgers(
+    settings: &Settings,
+    names: &str,
+    filter: &Filter,
+) -> Vec<(String, ExpenseTracker)> {
+    let ledgers = if names == "all" {
+        settings.config.ledgers()
+    } else {
+        names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| match settings.config.ledger(name) {
+                Some(path) => (name.to_string(), path),
+                None => {
+                    eprintln!("ERROR 0x15: {}.", config::unknown_ledger(name));
+                    process::exit(1);
+                }
+            })
+            .collect()
+    };
+
+    ledgers
+        .into_iter()
+        .map(|(name, path)| {
+            let mut tracker = open_tracker(settings, &path, LockMode::Shared);
+            load_tracker(&mut tracker, Some(filter));
+            for diagnostic in &tracker.diagnostics {
+                eprintln!("WARNING: {}", diagnostic);
+            }
+            (name, tracker)
+        })
+        .collect()
+}
+
+/// Loads the whole ledger, or only what `filter` can narrow down through the storage.
+fn load_tracker(tracker: &mut ExpenseTracker, filter: Option<&Filter>) {
+    let result = match filter {
+        Some(filter) => tracker.load_matching(&filter.range, filter.category.as_deref()),
+        None => tracker.load(),
+    };
+    if let Err(err) = result {
+        eprintln!("ERROR 0x0E: Cannot load expenses: {}.", err);
+        process::exit(1);
+    }
+}
+
+/// Warns about unreadable entries, and stops commands that would rewrite the ledger
+/// without them unless --force was given.
+fn check_diagnostics(tracker: &ExpenseTracker, args: &[String], force: bool) {
+    if tracker.diagnostics.is_empty() || matches!(args[1].as_str(), "doctor" | "check") {
+        return;
+    }
+    for diagnostic in &tracker.diagnostics {
+        eprintln!("WARNING: {}", diagnostic);
+    }
+    if writes_ledger(args) && !force {
+        eprintln!(
+            "ERROR 0x10: Refusing to modify {} because {} line(s) could not be read. \
+             Run `doctor` to review them or pass --force to quarantine them.",
+            tracker.file_name.display(),
+            tracker.diagnostics.len()
+        );
+        process::exit(1);
+    }
+}
+
+fn ledger_command(args: &[String], settings: &Settings) {
+    let mut config = settings.config.clone();
+    let result = match (args.get(2).map(String::as_str), args.get(3), args.get(4)) {
+        (Some("list") | None, None, None) => {
+            let mut rows = Rows::new(["name", "path", "expenses", "active"]);
+            if output::current().is_table() {
+                println!("#   {:<16}{:>10}  Path", "Ledger", "Expenses");
+            }
+            for (name, path) in config.ledgers() {
+                let active = path == settings.file_name;
+                let count = if path.exists() {
+                    Backend::detect(&path)
+                        .open(&path)
+                        .and_then(|mut storage| storage.load())
+                        .map(|loaded| loaded.expenses.iter().filter(|e| !e.is_trashed()).count())
+                        .ok()
+                } else {
+                    Some(0)
+                };
+                if output::current().is_table() {
+                    println!(
+                        "# {} {:<16}{:>10}  {}",
+                        if active { "*" } else { " " },
+                        name,
+                        count.map_or("?".to_string(), |count| count.to_string()),
+                        path.display()
+                    );
+                }
+                rows.push(vec![
+                    json!(name),
+                    json!(path.display().to_string()),
+                    json!(count),
+                    json!(active),
+                ]);
+            }
+            emit(&rows, false);
+            return;
+        }
+        (Some("create"), Some(name), path) => {
+            let path = match path.map(String::as_str) {
+                None => None,
+                Some("--path") if args.len() == 6 => Some(Path::new(&args[5])),
+                Some(_) => {
+                    eprintln!("ERROR 0x15: usage: ledger create NAME [--path FILE].");
+                    process::exit(1);
+                }
+            };
+            config
+                .add_ledger(name, path)
+                .and_then(|path| create_ledger_file(&path).map(|()| path))
+                .and_then(|path| config.save().map(|()| path))
+                .map(|path| {
+                    (
+                        format!("# Ledger \"{}\" created at {}", name, path.display()),
+                        ledger_record(name, &path),
+                    )
+                })
+        }
+        (Some("rename"), Some(old), Some(new)) => config
+            .rename_ledger(old, new)
+            .and_then(|new| config.save().map(|()| new))
+            .map(|new| {
+                let path = config.ledger(&new).unwrap_or_default();
+                (
+                    format!("# Ledger \"{}\" renamed to \"{}\"", old, new),
+                    ledger_record(&new, &path),
+                )
+            }),
+        (Some("remove"), Some(name), None) => config
+            .remove_ledger(name)
+            .and_then(|path| config.save().map(|()| path))
+            .map(|path| {
+                (
+                    format!(
+                        "# Ledger \"{}\" removed; its file is kept at {}",
+                        name,
+                        path.display()
+                    ),
+                    ledger_record(name, &path),
+                )
+            }),
+        _ => Err(
+            "usage: ledger [list | create NAME [--path FILE] | rename OLD NEW | remove NAME]"
+                .to_string(),
+        ),
+    };
+
+    match result {
+        Ok((message, record)) => {
+            note!("{}", message);
+            emit(&record, true);
+        }
+        Err(err) => {
+            eprintln!("ERROR 0x15: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn ledger_record(name: &str, path: &Path) -> Rows {
+    Rows::record([
+        ("name", json!(name)),
+        ("path", json!(path.display().to_string())),
+    ])
+}
+
+/// Starts an empty ledger so a freshly created one shows up in listings; an existing file
+/// is adopted as is.
+fn create_ledger_file(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let contents = format!("{}\n", record::header(1));
+    atomic::write_file(path, contents.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn config_command(args: &[String], mut config: Config) {
+    let result = match (args.get(2).map(String::as_str), args.get(3), args.get(4)) {
+        (Some("show") | None, None, None) => {
+            match config.path() {
+                Some(path) if path.exists() => note!("# Config file: {}", path.display()),
+                Some(path) => note!("# Config file: {} (not created yet)", path.display()),
+                None => note!("# Config file: none (set HOME or XDG_CONFIG_HOME)"),
+            }
+            let mut rows = Rows::new(["key", "value", "default"]);
+            for key in Config::all_keys() {
+                let value = config.effective(&key).unwrap_or_default();
+                let default = !matches!(config.get(&key), Ok(Some(_)));
+                if !output::current().is_table() {
+                    rows.push(vec![json!(key), json!(value), json!(default)]);
+                } else if default {
+                    println!("# {:<22}{} (default)", key, value);
+                } else {
+                    println!("# {:<22}{}", key, value);
+                }
+            }
+            emit(&rows, false);
+            return;
+        }
+        (Some("get"), Some(key), None) => match config.effective(key) {
+            Ok(value) if output::current().is_table() => {
+                println!("{}", value);
+                return;
+            }
+            Ok(value) => Ok((String::new(), config_record(key, &value))),
+            Err(err) => Err(err),
+        },
+        (Some("set"), Some(key), Some(value)) => config
+            .set(key, value)
+            .and_then(|()| config.save())
+            .map(|()| {
+                let value = config.effective(key).unwrap_or_default();
+                (
+                    format!("# Set {} = {}", key, value),
+                    config_record(key, &value),
+                )
+            }),
+        (Some("unset"), Some(key), None) => {
+            config.unset(key).and_then(|()| config.save()).map(|()| {
+                let value = config.effective(key).unwrap_or_default();
+                (format!("# Unset {}", key), config_record(key, &value))
+            })
+        }
+        _ => Err("usage: config [show | get KEY | set KEY VALUE | unset KEY]".to_string()),
+    };
+
+    match result {
+        Ok((message, record)) => {
+            if !message.is_empty() {
+                note!("{}", message);
+            }
+            emit(&record, true);
+        }
+        Err(err) => {
+            eprintln!("ERROR 0x13: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn config_record(key: &str, value: &str) -> Rows {
+    Rows::record([("key", json!(key)), ("value", json!(value))])
+}
+
+/// Writes `rows` in the machine-readable output format; the table format prints its own
+/// lines, so there is nothing to do.
+fn emit(rows: &Rows, single: bool) {
+    let format = output::current();
+    if format.is_table() {
+        return;
+    }
+    if let Err(err) = rows.write(format, single) {
+        eprintln!("ERROR 0x14: Cannot write output: {}.", err);
+        process::exit(1);
+    }
+}
+
+/// Whether the command in `args` changes the expenses in the ledger.
+fn writes_ledger(args: &[String]) -> bool {
+    match args[1].as_str() {
+        command if MUTATING_COMMANDS.contains(&command) => true,
+        "trash" => args.get(2).is_some_and(|subcommand| subcommand == "purge"),
+        "bulk" => args.iter().any(|arg| arg == "--yes"),
+        "import" => {
+            args.get(2).is_some_and(|subcommand| subcommand == "csv")
+                && args.iter().any(|arg| arg == "--yes")
+        }
+        _ => false,
+    }
+}
+
+/// Commands that may write anything take the ledger lock exclusively; everything else
+/// shares it so concurrent reports do not block each other.
+fn lock_mode(args: &[String]) -> LockMode {
+    let subcommand = args.get(2).map(String::as_str);
+    let writes = match args[1].as_str() {
+        _ if writes_ledger(args) => true,
+        "doctor" | "check" => args.iter().any(|arg| arg == "--quarantine"),
+        "rates" | "category" => !matches!(subcommand, None | Some("list")),
+        _ => false,
+    };
+    if writes {
+        LockMode::Exclusive
+    } else {
+        LockMode::Shared
+    }
+}
+
+/// Locks a file that every ledger in the directory shares, such as the rate table, for the
+/// rest of the command. The ledger's own lock does not cover other ledgers' commands.
+fn lock_shared_file(path: &Path, what: &str, args: &[String], settings: &Settings) -> LedgerLock {
+    match LedgerLock::acquire(path, lock_mode(args), settings.lock_timeout) {
+        Ok(lock) => lock,
+        Err(err) => {
+            eprintln!("ERROR 0x12: Cannot lock {}: {}.", what, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+
+    let settings = parse_settings(&mut args);
+
+    if args.len() < 2 {
+        eprintln!("ERROR 0x00: Insufficient Arguments.");
+        process::exit(1);
+    }
+
+    let command = &args[1];
+    if command == "config" {
+        config_command(&args, settings.config);
+        return;
+    }
+    if command == "ledger" {
+        ledger_command(&args, &settings);
+        return;
+    }
+    let mut tracker = open_tracker(&settings, &settings.file_name, lock_mode(&args));
+    let force = settings.force;
+    // list, summary and report only load the expenses their filters select.
+    if !matches!(command.as_str(), "list" | "summary" | "report") {
+        load_tracker(&mut tracker, None);
+        check_diagnostics(&tracker, &args, force);
+    }
+
+    match command.as_str() {
+        "add" => {
+            let mut date = tracker.get_current_date();
+            let mut description = String::new();
+            let mut amount = None;
+            let mut currency = tracker.config.currency();
+            let mut category = None;
+            let mut tags = BTreeSet::new();
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--description" if i + 1 < args.len() => {
+                        description = args[i + 1].clone();
+                        i += 1;
+                    }
+                    "--amount" if i + 1 < args.len() => {
+                        amount = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--currency" if i + 1 < args.len() => {
+                        currency = parse_currency(&args[i + 1]);
+                        i += 1;
+                    }
+                    "--date" if i + 1 < args.len() => {
+                        date = parse_date(&tracker, &args[i + 1]);
+                        i += 1;
+                    }
+                    "--category" if i + 1 < args.len() => {
+                        category = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--tag" if i + 1 < args.len() => {
+                        tags.insert(parse_tag(&args[i + 1]));
+                        i += 1;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+
+            let category = match category {
+                Some(name) => resolve_category(&tracker, &name),
+                None => UNCATEGORIZED.to_string(),
+            };
+
+            let amount = amount.map(|value| match Money::parse(&value, currency) {
+                Ok(value) => value,
+                Err(err) => {
+                    eprintln!("ERROR 0x04: Invalid amount: {}.", err);
+                    process::exit(1);
+                }
+            });
+
+            let amount = match amount {
+                Some(amount) if amount.is_positive() && !description.is_empty() => amount,
+                _ => {
+                    eprintln!("ERROR 0x01: Invalid arguments for adding an expense.");
+                    process::exit(1);
+                }
+            };
+
+            tracker.add_expense(date, description, amount, category, tags);
+        }
+        "list" => {
+            let mut query = QueryOptions::default();
+
+            let mut i = 2;
+            while i < args.len() {
+                i += query.take(&args[i], args.get(i + 1)).max(1);
+            }
+
+            let query = resolve_query(&tracker, &query);
+            load_tracker(&mut tracker, Some(&query.filter));
+            check_diagnostics(&tracker, &args, force);
+            tracker.list_expenses(&query);
+        }
+        "summary" => {
+            let mut filter = FilterOptions::default();
+            let mut base: Option<Currency> = None;
+            let mut by_category = false;
+            let mut ledgers = None;
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--base" if i + 1 < args.len() => {
+                        base = Some(parse_currency(&args[i + 1]));
+                        i += 1;
+                    }
+                    "--ledgers" if i + 1 < args.len() => {
+                        ledgers = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--by" if i + 1 < args.len() => {
+                        if args[i + 1] != "category" {
+                            eprintln!("ERROR 0x08: Unknown summary grouping \"{}\".", args[i + 1]);
+                            process::exit(1);
+                        }
+                        by_category = true;
+                        i += 1;
+                    }
+                    _ => i += filter.take(&args[i], args.get(i + 1)).saturating_sub(1),
+                }
+                i += 1;
+            }
+
+            let filter = resolve_filter(&tracker, &filter);
+            load_tracker(&mut tracker, Some(&filter));
+            check_diagnostics(&tracker, &args, force);
+            let ledgers = ledgers.map(|names| open_ledgers(&settings, &names, &filter));
+            let rates = base.map(|_| load_rates(&tracker.rates_path()));
+            let base = base.zip(rates.as_ref());
+            let result = match &ledgers {
+                Some(ledgers) => ExpenseTracker::sum_ledgers(ledgers, &filter, base, by_category),
+                None => tracker.sum_expenses(&filter, base, by_category),
+            };
+            if let Err(err) = result {
+                eprintln!("ERROR 0x07: Cannot summarize expenses: {}.", err);
+                process::exit(1);
+            }
+        }
+        "report" => {
+            let mut filter = FilterOptions::default();
+            let mut period = Period::Month;
+            let mut base: Option<Currency> = None;
+            let mut by_category = false;
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--period" if i + 1 < args.len() => {
+                        period = match Period::parse(&args[i + 1]) {
+                            Ok(period) => period,
+                            Err(err) => {
+                                eprintln!("ERROR 0x0F: Invalid report period: {}.", err);
+                                process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    }
+                    "--base" if i + 1 < args.len() => {
+                        base = Some(parse_currency(&args[i + 1]));
+                        i += 1;
+                    }
+                    "--by" if i + 1 < args.len() => {
+                        if args[i + 1] != "category" {
+                            eprintln!("ERROR 0x08: Unknown report grouping \"{}\".", args[i + 1]);
+                            process::exit(1);
+                        }
+                        by_category = true;
+                        i += 1;
+                    }
+                    _ => i += filter.take(&args[i], args.get(i + 1)).saturating_sub(1),
+                }
+                i += 1;
+            }
+
+            let mut filter = resolve_filter(&tracker, &filter);
+            if filter.range.is_unbounded() {
+                let year = DateRange::year(tracker.get_current_date().year());
+                filter.range = year.unwrap_or(filter.range);
+            }
+            load_tracker(&mut tracker, Some(&filter));
+            check_diagnostics(&tracker, &args, force);
+            let rates = base.map(|_| load_rates(&tracker.rates_path()));
+            let base = base.zip(rates.as_ref());
+            if let Err(err) = tracker.report_expenses(&filter, period, base, by_category) {
+                eprintln!("ERROR 0x07: Cannot build report: {}.", err);
+                process::exit(1);
+            }
+        }
+        "rates" => {
+            let path = tracker.rates_path();
+            let _lock = lock_shared_file(&path, "rates", &args, &settings);
+            let mut rates = load_rates(&path);
+
+            match args.get(2).map(String::as_str) {
+                Some("add") => {
+                    let mut date = tracker.get_current_date();
+                    let mut from = None;
+                    let mut to = None;
+                    let mut rate = None;
+
+                    let mut i = 3;
+                    while i < args.len() {
+                        match args[i].as_str() {
+                            "--date" if i + 1 < args.len() => {
+                                date = parse_date(&tracker, &args[i + 1]);
+                                i += 1;
+                            }
+                            "--from" if i + 1 < args.len() => {
+                                from = Some(parse_currency(&args[i + 1]));
+                                i += 1;
+                            }
+                            "--to" if i + 1 < args.len() => {
+                                to = Some(parse_currency(&args[i + 1]));
+                                i += 1;
+                            }
+                            "--rate" if i + 1 < args.len() => {
+                                rate = Some(args[i + 1].clone());
+                                i += 1;
+                            }
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+
+                    let (Some(from), Some(to), Some(rate)) = (from, to, rate) else {
+                        eprintln!("ERROR 0x06: Invalid arguments for adding a rate.");
+                        process::exit(1);
+                    };
+                    match ExchangeRate::new(date, from, to, &rate) {
+                        Ok(rate) => {
+                            rates.insert(rate);
+                            save_rates(&rates);
+                            note!(
+                                "# Rate added: 1 {} = {} {} on {}",
+                                rate.from,
+                                rate,
+                                rate.to,
+                                rate.date
+                            );
+                            let mut rows = Rows::new(RATE_COLUMNS);
+                            rows.push(rate_values(&rate));
+                            emit(&rows, true);
+                        }
+                        Err(err) => {
+                            eprintln!("ERROR 0x06: Invalid rate: {}.", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+                Some("import") => {
+                    let Some(file) = args.get(3) else {
+                        eprintln!("ERROR 0x06: Missing CSV file to import rates from.");
+                        process::exit(1);
+                    };
+                    match rates.import_csv(Path::new(file)) {
+                        Ok(count) => {
+                            save_rates(&rates);
+                            note!("# Imported {} rate(s) from {}", count, file);
+                            emit(
+                                &Rows::record([("file", json!(file)), ("imported", json!(count))]),
+                                true,
+                            );
+                        }
+                        Err(err) => {
+                            eprintln!("ERROR 0x06: Cannot import rates: {}.", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+                Some("list") | None => {
+                    if !output::current().is_table() {
+                        let mut rows = Rows::new(RATE_COLUMNS);
+                        for rate in rates.rates() {
+                            rows.push(rate_values(rate));
+                        }
+                        emit(&rows, false);
+                    } else if rates.rates().is_empty() {
+                        note!("# No rates to display.");
+                    } else {
+                        println!("# {:>12}{:>6}{:>6}{:>16}", "Date", "From", "To", "Rate");
+                        for rate in rates.rates() {
+                            println!(
+                                "# {:>12}{:>6}{:>6}{:>16}",
+                                rate.date.to_string(),
+                                rate.from,
+                                rate.to,
+                                rate.to_string()
+                            );
+                        }
+                    }
+                }
+                Some(_) => {
+                    eprintln!("ERROR 0x03: Unknown command.");
+                    process::exit(1);
+                }
+            }
+        }
+        "delete" => {
+            let mut id = 0;
+
+            let mut i = 2;
+            while i < args.len() {
+                if args[i] == "--id" && i + 1 < args.len() {
+                    id = args[i + 1].parse().unwrap_or(0);
+                    i += 1;
+                }
+                i += 1;
+            }
+
+            if id <= 0 {
+                eprintln!("ERROR 0x02: Invalid ID for deletion.");
+                process::exit(1);
+            }
+
+            tracker.delete_expense(id);
+        }
+        "restore" => {
+            let mut id = 0;
+
+            let mut i = 2;
+            while i < args.len() {
+                if args[i] == "--id" && i + 1 < args.len() {
+                    id = args[i + 1].parse().unwrap_or(0);
+                    i += 1;
+                }
+                i += 1;
+            }
+
+            if id <= 0 {
+                eprintln!("ERROR 0x18: Invalid ID for restoring.");
+                process::exit(1);
+            }
+
+            tracker.restore_expense(id);
+        }
+        "bulk" => {
+            let (outcome, category, start) = match args.get(2).map(String::as_str) {
+                Some("delete") => ("moved to the trash".to_string(), None, 3),
+                Some("categorize") if args.len() > 3 => {
+                    let category = resolve_category(&tracker, &args[3]);
+                    (format!("moved to \"{}\"", category), Some(category), 4)
+                }
+                _ => {
+                    eprintln!(
+                        "ERROR 0x19: usage: bulk delete FILTERS [--yes] | \
+                         bulk categorize CATEGORY FILTERS [--yes]."
+                    );
+                    process::exit(1);
+                }
+            };
+            let mut filter = FilterOptions::default();
+            let mut confirmed = false;
+
+            let mut i = start;
+            while i < args.len() {
+                if args[i] == "--yes" {
+                    confirmed = true;
+                    i += 1;
+                    continue;
+                }
+                match filter.take(&args[i], args.get(i + 1)) {
+                    0 => {
+                        eprintln!("ERROR 0x19: Unknown filter \"{}\".", args[i]);
+                        process::exit(1);
+                    }
+                    used => i += used,
+                }
+            }
+
+            let filter = resolve_filter(&tracker, &filter);
+            if filter.is_empty() {
+                eprintln!("ERROR 0x19: Bulk changes need at least one filter.");
+                process::exit(1);
+            }
+            let deleted = Local::now().fixed_offset();
+            tracker.bulk_update(&filter, confirmed, &outcome, |e| match &category {
+                Some(category) => e.category = category.clone(),
+                None => e.deleted = Some(deleted),
+            });
+        }
+        "import" => match (args.get(2).map(String::as_str), args.get(3), args.get(4)) {
+            (Some("csv"), Some(file), _) => {
+                let mut options = ImportOptions::default();
+                let mut profile_name = None;
+                let mut save_as = None;
+                let mut keep_duplicates = false;
+                let mut confirmed = false;
+
+                let mut i = 4;
+                while i < args.len() {
+                    match args[i].as_str() {
+                        "--profile" if i + 1 < args.len() => {
+                            profile_name = Some(args[i + 1].clone());
+                            i += 2;
+                        }
+                        "--save-profile" if i + 1 < args.len() => {
+                            save_as = Some(args[i + 1].clone());
+                            i += 2;
+                        }
+                        "--keep-duplicates" => {
+                            keep_duplicates = true;
+                            i += 1;
+                        }
+                        "--yes" => {
+                            confirmed = true;
+                            i += 1;
+                        }
+                        flag => match options.take(flag, args.get(i + 1)) {
+                            0 => {
+                                eprintln!("ERROR 0x1A: Unknown import option \"{}\".", flag);
+                                process::exit(1);
+                            }
+                            used => i += used,
+                        },
+                    }
+                }
+
+                let saved = match profile_name.map(|name| tracker.config.import_profile(&name)) {
+                    Some(Ok(profile)) => profile.clone(),
+                    Some(Err(err)) => {
+                        eprintln!("ERROR 0x1A: {}.", err);
+                        process::exit(1);
+                    }
+                    None => ImportProfile::default(),
+                };
+                let profile = match options.resolve() {
+                    Ok(flags) => saved.overlay(&flags),
+                    Err(err) => {
+                        eprintln!("ERROR 0x1A: Invalid import option: {}.", err);
+                        process::exit(1);
+                    }
+                };
+                let statement = match import::read_csv(
+                    Path::new(file),
+                    &profile,
+                    tracker.config.date_format(),
+                    tracker.config.currency(),
+                ) {
+                    Ok(statement) => statement,
+                    Err(err) => {
+                        eprintln!("ERROR 0x1A: Cannot import expenses: {}.", err);
+                        process::exit(1);
+                    }
+                };
+                let category = match &profile.category {
+                    Some(name) => resolve_category(&tracker, name),
+                    None => UNCATEGORIZED.to_string(),
+                };
+
+                if let Some(name) = save_as {
+                    let mut config = tracker.config.clone();
+                    let result = config
+                        .save_import_profile(&name, profile)
+                        .and_then(|name| config.save().map(|()| name));
+                    match result {
+                        Ok(name) => {
+                            note!("# Saved the column mapping as import profile \"{}\"", name)
+                        }
+                        Err(err) => {
+                            eprintln!("ERROR 0x13: Cannot save import profile: {}.", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+                tracker.import_statement(statement, &category, keep_duplicates, confirmed);
+            }
+            (Some("profile"), Some(subcommand), name) if subcommand == "list" && name.is_none() => {
+                let mut rows = Rows::new(["name"].into_iter().chain(ImportProfile::FIELDS));
+                for (name, profile) in &tracker.config.imports {
+                    let fields = profile.fields();
+                    if output::current().is_table() {
+                        let set: Vec<String> = ImportProfile::FIELDS
+                            .iter()
+                            .zip(&fields)
+                            .filter_map(|(key, value)| Some(format!("{}={}", key, value.as_ref()?)))
+                            .collect();
+                        println!("# {:<16}{}", name, set.join(", "));
+                    }
+                    rows.push(
+                        [json!(name)]
+                            .into_iter()
+                            .chain(fields.iter().map(|value| json!(value)))
+                            .collect(),
+                    );
+                }
+                if tracker.config.imports.is_empty() {
+                    note!(
+                        "# No import profiles. Save one with `import csv FILE ... --save-profile NAME`."
+                    );
+                }
+                emit(&rows, false);
+            }
+            (Some("profile"), Some(subcommand), Some(name))
+                if subcommand == "remove" && args.len() == 5 =>
+            {
+                let mut config = tracker.config.clone();
+                let result = config
+                    .remove_import_profile(name)
+                    .and_then(|_| config.save());
+                match result {
+                    Ok(()) => {
+                        note!("# Import profile \"{}\" removed", name);
+                        emit(&Rows::record([("name", json!(name))]), true);
+                    }
+                    Err(err) => {
+                        eprintln!("ERROR 0x1A: {}.", err);
+                        process::exit(1);
+                    }
+                }
+            }
+            _ => {
+                eprintln!(
+                    "ERROR 0x1A: usage: import csv FILE [MAPPING] [--profile NAME] \
+                     [--save-profile NAME] [--keep-duplicates] [--yes] | \
+                     import profile list | import profile remove NAME."
+                );
+                process::exit(1);
+            }
+        },
+        "trash" => match (args.get(2).map(String::as_str), args.get(3), args.get(4)) {
+            (Some("list") | None, None, None) => tracker.list_trash(),
+            (Some("purge"), Some(flag), Some(age)) if flag == "--older-than" && args.len() == 5 => {
+                let cutoff = match dates::parse_age(age, tracker.get_current_date()) {
+                    Ok(cutoff) => cutoff,
+                    Err(err) => {
+                        eprintln!("ERROR 0x18: Invalid --older-than: {}.", err);
+                        process::exit(1);
+                    }
+                };
+                tracker.purge_trash(cutoff);
+            }
+            _ => {
+                eprintln!("ERROR 0x18: usage: trash [list] | trash purge --older-than AGE.");
+                process::exit(1);
+            }
+        },
+        "edit" | "update" => {
+            let mut id = 0;
+            let mut edit = ExpenseEdit::default();
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--id" if i + 1 < args.len() => {
+                        id = args[i + 1].parse().unwrap_or(0);
+                        i += 1;
+                    }
+                    "--date" if i + 1 < args.len() => {
+                        edit.date = Some(parse_date(&tracker, &args[i + 1]));
+                        i += 1;
+                    }
+                    "--description" if i + 1 < args.len() => {
+                        edit.description = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--amount" if i + 1 < args.len() => {
+                        edit.amount = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--currency" if i + 1 < args.len() => {
+                        edit.currency = Some(parse_currency(&args[i + 1]));
+                        i += 1;
+                    }
+                    "--category" if i + 1 < args.len() => {
+                        edit.category = Some(resolve_category(&tracker, &args[i + 1]));
+                        i += 1;
+                    }
+                    "--tag" if i + 1 < args.len() => {
+                        edit.add_tags.push(parse_tag(&args[i + 1]));
+                        i += 1;
+                    }
+                    "--untag" if i + 1 < args.len() => {
+                        edit.remove_tags.push(parse_tag(&args[i + 1]));
+                        i += 1;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+
+            if id <= 0 {
+                eprintln!("ERROR 0x0B: Invalid ID for editing.");
+                process::exit(1);
+            }
+            if edit.description.as_ref().is_some_and(|d| d.is_empty()) {
+                eprintln!("ERROR 0x0B: Description cannot be empty.");
+                process::exit(1);
+            }
+
+            if let Err(err) = tracker.edit_expense(id, edit) {
+                eprintln!("ERROR 0x04: Invalid amount: {}.", err);
+                process::exit(1);
+            }
+        }
+        "migrate" => {
+            let mut backend = None;
+            let mut target = None;
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--to" if i + 1 < args.len() => {
+                        backend = match Backend::parse(&args[i + 1]) {
+                            Ok(backend) => Some(backend),
+                            Err(err) => {
+                                eprintln!("ERROR 0x16: {}.", err);
+                                process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    }
+                    "--path" if i + 1 < args.len() => {
+                        target = Some(PathBuf::from(&args[i + 1]));
+                        i += 1;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+
+            let Some(backend) = backend else {
+                eprintln!("ERROR 0x16: usage: migrate --to text|sqlite [--path FILE].");
+                process::exit(1);
+            };
+            if Backend::detect(&tracker.file_name) == backend {
+                eprintln!(
+                    "ERROR 0x16: {} already uses {} storage.",
+                    tracker.file_name.display(),
+                    backend.name()
+                );
+                process::exit(1);
+            }
+            let target =
+                target.unwrap_or_else(|| tracker.file_name.with_extension(backend.extension()));
+            if target.exists() {
+                eprintln!(
+                    "ERROR 0x16: {} already exists; choose another file with --path.",
+                    target.display()
+                );
+                process::exit(1);
+            }
+
+            let result = backend
+                .open(&target)
+                .and_then(|mut storage| storage.save(&tracker.expenses, tracker.next_id))
+                .and_then(|()| Journal::copy(&tracker.file_name, &target));
+            if let Err(err) = result {
+                let _ = fs::remove_file(&target);
+                eprintln!("ERROR 0x16: Cannot migrate expenses: {}.", err);
+                process::exit(1);
+            }
+            note!(
+                "# Copied {} expense(s) from {} to {} ({} storage)",
+                tracker.expenses.len(),
+                tracker.file_name.display(),
+                target.display(),
+                backend.name()
+            );
+            emit(
+                &Rows::record([
+                    ("from", json!(tracker.file_name.display().to_string())),
+                    ("to", json!(target.display().to_string())),
+                    ("storage", json!(backend.name())),
+                    ("expenses", json!(tracker.expenses.len())),
+                ]),
+                true,
+            );
+
+            let mut config = tracker.config.clone();
+            match config.move_ledger(&tracker.file_name, &target) {
+                Some(name) => match config.save() {
+                    Ok(()) => note!(
+                        "# Ledger \"{}\" now uses {}; the old file is kept as a backup",
+                        name,
+                        target.display()
+                    ),
+                    Err(err) => {
+                        eprintln!("ERROR 0x13: Cannot update config: {}.", err);
+                        process::exit(1);
+                    }
+                },
+                None => note!("# Pass --file {} to use it", target.display()),
+            }
+        }
+        "undo" | "redo" => {
+            let result = if command == "undo" {
+                tracker.undo()
+            } else {
+                tracker.redo()
+            };
+            if let Err(err) = result {
+                eprintln!("ERROR 0x17: {}.", err);
+                process::exit(1);
+            }
+        }
+        "history" => {
+            let mut limit = 20;
+
+            let mut i = 2;
+            while i < args.len() {
+                if args[i] == "--limit" && i + 1 < args.len() {
+                    limit = match args[i + 1].parse::<usize>() {
+                        Ok(limit) => limit,
+                        Err(_) => {
+                            eprintln!("ERROR 0x17: Invalid --limit \"{}\".", args[i + 1]);
+                            process::exit(1);
+                        }
+                    };
+                    i += 1;
+                }
+                i += 1;
+            }
+
+            if let Err(err) = tracker.history(limit) {
+                eprintln!("ERROR 0x17: Cannot read history: {}.", err);
+                process::exit(1);
+            }
+        }
+        "doctor" | "check" => {
+            let quarantine = args.iter().skip(2).any(|arg| arg == "--quarantine");
+
+            let mut rows = Rows::new(["file", "line", "reason", "content"]);
+            for diagnostic in &tracker.diagnostics {
+                rows.push(vec![
+                    json!(diagnostic.file),
+                    json!(diagnostic.line),
+                    json!(diagnostic.reason),
+                    json!(diagnostic.content),
+                ]);
+            }
+            emit(&rows, false);
+
+            if tracker.diagnostics.is_empty() {
+                note!(
+                    "# No problems found in {} ({} expense(s))",
+                    tracker.file_name.display(),
+                    tracker.expenses.len()
+                );
+                return;
+            }
+
+            if output::current().is_table() {
+                for diagnostic in &tracker.diagnostics {
+                    println!("# {}", diagnostic);
+                    println!("#     {}", diagnostic.content);
+                }
+            }
+
+            if quarantine {
+                match tracker.quarantine_bad_lines() {
+                    Ok(count) => note!(
+                        "# Moved {} unreadable line(s) to {}",
+                        count,
+                        tracker.quarantine_path().display()
+                    ),
+                    Err(err) => {
+                        eprintln!("ERROR 0x10: Cannot quarantine unreadable lines: {}.", err);
+                        process::exit(1);
+                    }
+                }
+            } else {
+                note!(
+                    "# {} line(s) could not be read. Run `doctor --quarantine` to move them to {}.",
+                    tracker.diagnostics.len(),
+                    tracker.quarantine_path().display()
+                );
+            }
+        }
+        "category" => {
+            let _lock =
+                lock_shared_file(&tracker.categories_path(), "categories", &args, &settings);
+            let mut categories = load_categories(&tracker);
+
+            let result = match (args.get(2).map(String::as_str), args.get(3), args.get(4)) {
+                (Some("add"), Some(name), None) => categories.add(name).map(|name| {
+                    (
+                        format!("# Category \"{}\" added", name),
+                        Rows::record([("category", json!(name))]),
+                    )
+                }),
+                (Some("alias"), Some(alias), Some(name)) => {
+                    categories.add_alias(alias, name).map(|(alias, name)| {
+                        (
+                            format!("# \"{}\" is now an alias for \"{}\"", alias, name),
+                            Rows::record([("alias", json!(alias)), ("category", json!(name))]),
+                        )
+                    })
+                }
+                (Some("remove"), Some(name), None) => categories.remove(name).map(|name| {
+                    (
+                        format!("# Category \"{}\" removed", name),
+                        Rows::record([("category", json!(name))]),
+                    )
+                }),
+                (Some("list") | None, None, None) => {
+                    if output::current().is_table() {
+                        println!("# {:>16}  Aliases", "Category");
+                        for category in categories.entries() {
+                            println!("# {:>16}  {}", category.name, category.aliases.join(", "));
+                        }
+                    } else {
+                        let mut rows = Rows::new(["category", "aliases"]);
+                        for category in categories.entries() {
+                            rows.push(vec![json!(category.name), json!(category.aliases)]);
+                        }
+                        emit(&rows, false);
+                    }
+                    return;
+                }
+                _ => {
+                    eprintln!("ERROR 0x09: Invalid arguments for managing categories.");
+                    process::exit(1);
+                }
+            };
+
+            match result.and_then(|outcome| categories.save().map(|_| outcome)) {
+                Ok((message, record)) => {
+                    note!("{}", message);
+                    emit(&record, true);
+                }
+                Err(err) => {
+                    eprintln!("ERROR 0x09: Cannot update categories: {}.", err);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => {
+            eprintln!("ERROR 0x03: Unknown command.");
+            process::exit(1);
+        }
+    }
+
+    let changes = tracker.changes.clone();
+    if let Err(err) = tracker.commit(command) {
+        eprintln!("ERROR 0x11: Cannot save expenses: {}.", err);
+        process::exit(1);
+    }
+    if writes_ledger(&args) && command != "migrate" {
+        let single = matches!(
+            command.as_str(),
+            "add" | "edit" | "update" | "delete" | "restore"
+        );
+        emit(&tracker.change_rows(&changes), single);
+    }
+}
+
+fn too_large(currency: Currency) -> String {
+    format!("the {} total is too large", currency)
+}
+
+/// Adds an amount to a report cell holding minor units.
+fn add_minor(cell: i64, amount: Money) -> Result<i64, String> {
+    cell.checked_add(amount.minor())
+        .ok_or_else(|| too_large(amount.currency()))
+}
+
+/// What a change did to its expense, e.g. "added" or "trashed".
+fn change_kind(change: &Change) -> &'static str {
+    match (&change.before, &change.after) {
+        (None, Some(_)) => "added",
+        (Some(e), None) if e.is_trashed() => "purged",
+        (Some(_), None) => "deleted",
+        (Some(before), Some(after)) if before.is_trashed() != after.is_trashed() => {
+            if after.is_trashed() {
+                "trashed"
+            } else {
+                "restored"
+            }
+        }
+        (Some(_), Some(_)) => "edited",
+        (None, None) => "",
+    }
+}
+
+/// A one-line summary of what a journal entry changed.
+fn describe_entry(entry: &journal::Entry) -> String {
+    let describe = |change: &Change| match (&change.before, &change.after) {
+        (Some(before), Some(after)) if change_kind(change) == "edited" => {
+            let fields: Vec<&str> = after
+                .changes_from(before)
+                .into_iter()
+                .map(|(field, _, _)| field)
+                .collect();
+            format!("edited {} ({})", after.id, fields.join(", "))
+        }
+        (_, Some(e)) | (Some(e), None) => format!(
+            "{} {} \"{}\" {}",
+            change_kind(change),
+            e.id,
+            e.description,
+            format_money(e.amount)
+        ),
+        (None, None) => String::new(),
+    };
+    match entry.changes.as_slice() {
+        [] => String::new(),
+        [change] => describe(change),
+        [first, rest @ ..] => format!("{} and {} more", describe(first), rest.len()),
+    }
+}
+
+fn parse_currency(code: &str) -> Currency {
+    match Currency::from_code(code) {
+        Some(currency) => currency,
+        None => {
+            eprintln!("ERROR 0x05: Unknown currency \"{}\".", code);
+            process::exit(1);
+        }
+    }
+}
+
+const RATE_COLUMNS: [&str; 4] = ["date", "from", "to", "rate"];
+
+fn rate_values(rate: &ExchangeRate) -> Vec<Value> {
+    vec![
+        json!(rate.date.to_string()),
+        json!(rate.from.to_string()),
+        json!(rate.to.to_string()),
+        json!(rate.to_string()),
+    ]
+}
+
+fn load_rates(path: &Path) -> RateTable {
+    match RateTable::load(path) {
+        Ok(rates) => rates,
+        Err(err) => {
+            eprintln!("ERROR 0x06: Cannot load rates: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn save_rates(rates: &RateTable) {
+    if let Err(err) = rates.save() {
+        eprintln!("ERROR 0x06: Cannot save rates: {}.", err);
+        process::exit(1);
+    }
+}
+
+fn load_categories(tracker: &ExpenseTracker) -> Categories {
+    let path = tracker.categories_path();
+    match Categories::load(&path, tracker.config.categories.as_deref()) {
+        Ok(categories) => categories,
+        Err(err) => {
+            eprintln!("ERROR 0x09: Cannot load categories: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn resolve_category(tracker: &ExpenseTracker, name: &str) -> String {
+    let categories = load_categories(tracker);
+    match categories.resolve(name) {
+        Some(category) => category.to_string(),
+        None => {
+            eprintln!(
+                "ERROR 0x09: Unknown category \"{}\". Add it with `category add {}`.",
+                name, name
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// `path` with `suffix` appended to its file name, e.g. `expenses.txt.quarantine`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != before
+}
+
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == name)?;
+    if pos + 1 >= args.len() {
+        args.remove(pos);
+        return None;
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Some(value)
+}
+
+fn format_money(amount: Money) -> String {
+    format!("{}{}", amount.currency().symbol(), amount)
+}
+
+fn join_tags(tags: &BTreeSet<String>) -> String {
+    tags.iter().cloned().collect::<Vec<_>>().join(",")
+}
+
+fn parse_tag(tag: &str) -> String {
+    match categories::validate_name(tag) {
+        Ok(tag) => tag,
+        Err(err) => {
+            eprintln!("ERROR 0x0A: Invalid tag: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_date(tracker: &ExpenseTracker, date: &str) -> NaiveDate {
+    if let Ok(date) = NaiveDate::parse_from_str(date.trim(), tracker.config.date_format()) {
+        return date;
+    }
+    match dates::parse_date_arg(date, tracker.timezone.today()) {
+        Ok(date) => date,
+        Err(err) => {
+            eprintln!("ERROR 0x0C: Invalid date: {}.", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn resolve_filter(tracker: &ExpenseTracker, options: &FilterOptions) -> Filter {
+    let mut filter = options
+        .resolve(tracker.get_current_date())
+        .unwrap_or_else(|err| query_error(err));
+    filter.category = filter.category.map(|name| resolve_category(tracker, &name));
+    filter
+}
+
+fn resolve_query(tracker: &ExpenseTracker, options: &QueryOptions) -> Query {
+    let mut query = options
+        .resolve(tracker.get_current_date())
+        .unwrap_or_else(|err| query_error(err));
+    query.filter.category = query
+        .filter
+        .category
+        .map(|name| resolve_category(tracker, &name));
+    query
+}
+
+fn query_error(err: QueryError) -> ! {
+    match err {
+        QueryError::Range(err) => eprintln!("ERROR 0x0F: Invalid date range: {}.", err),
+        QueryError::Tags(err) => eprintln!("ERROR 0x0A: Invalid tag expression: {}.", err),
+        QueryError::Invalid(err) => eprintln!("ERROR 0x19: Invalid filter: {}.", err),
     }
+    process::exit(1);
 }
@@ -0,0 +1,349 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency {
+    code: &'static str,
+    exponent: u32,
+    symbol: &'static str,
+}
+
+const CURRENCIES: &[Currency] = &[
+    Currency::new("USD", 2, "$"),
+    Currency::new("EUR", 2, "€"),
+    Currency::new("GBP", 2, "£"),
+    Currency::new("JPY", 0, "¥"),
+    Currency::new("AUD", 2, "A$"),
+    Currency::new("CAD", 2, "C$"),
+    Currency::new("NZD", 2, "NZ$"),
+    Currency::new("CHF", 2, "CHF "),
+    Currency::new("CNY", 2, "CN¥"),
+    Currency::new("HKD", 2, "HK$"),
+    Currency::new("SGD", 2, "S$"),
+    Currency::new("INR", 2, "₹"),
+    Currency::new("KRW", 0, "₩"),
+    Currency::new("SEK", 2, "kr "),
+    Currency::new("NOK", 2, "kr "),
+    Currency::new("DKK", 2, "kr "),
+    Currency::new("BHD", 3, "BD "),
+    Currency::new("KWD", 3, "KD "),
+];
+
+impl Currency {
+    const fn new(code: &'static str, exponent: u32, symbol: &'static str) -> Currency {
+        Currency {
+            code,
+            exponent,
+            symbol,
+        }
+    }
+
+    pub const USD: Currency = CURRENCIES[0];
+
+    pub fn from_code(code: &str) -> Option<Currency> {
+        CURRENCIES
+            .iter()
+            .find(|c| c.code.eq_ignore_ascii_case(code))
+            .copied()
+    }
+
+    pub fn exponent(&self) -> u32 {
+        self.exponent
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        self.symbol
+    }
+
+    pub fn scale(&self) -> i64 {
+        10_i64.pow(self.exponent)
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    Invalid(String),
+    TooPrecise { value: String, currency: Currency },
+    Overflow(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Invalid(value) => write!(f, "\"{}\" is not a valid amount", value),
+            MoneyError::TooPrecise { value, currency } => write!(
+                f,
+                "\"{}\" has more than {} decimal place(s) allowed for {}",
+                value,
+                currency.exponent(),
+                currency
+            ),
+            MoneyError::Overflow(value) => write!(f, "\"{}\" is too large", value),
+        }
+    }
+}
+
+/// An exact amount of money, stored as an integer count of the currency's minor units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn zero(currency: Currency) -> Self {
+        Money { minor: 0, currency }
+    }
+
+    pub fn from_minor(minor: i64, currency: Currency) -> Self {
+        Money { minor, currency }
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor > 0
+    }
+
+    /// Parses a plain decimal such as `12.50`, rejecting more decimal places than the
+    /// currency has minor units.
+    pub fn parse(value: &str, currency: Currency) -> Result<Money, MoneyError> {
+        let invalid = || MoneyError::Invalid(value.to_string());
+        let trimmed = value.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+            || digits.ends_with('.')
+        {
+            return Err(invalid());
+        }
+
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > currency.exponent() as usize {
+            return Err(MoneyError::TooPrecise {
+                value: value.to_string(),
+                currency,
+            });
+        }
+
+        let overflow = || MoneyError::Overflow(value.to_string());
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| overflow())?
+        };
+        let padded = format!(
+            "{:0<width$}",
+            fraction,
+            width = currency.exponent() as usize
+        );
+        let fraction: i64 = if padded.is_empty() {
+            0
+        } else {
+            padded.parse().map_err(|_| invalid())?
+        };
+
+        let minor = whole
+            .checked_mul(currency.scale())
+            .and_then(|m| m.checked_add(fraction))
+            .ok_or_else(overflow)?;
+
+        Ok(Money {
+            minor: if negative { -minor } else { minor },
+            currency,
+        })
+    }
+
+    /// Parses an amount given without a currency, such as a bound in a filter, keeping as
+    /// many decimal places as any supported currency has.
+    pub fn parse_bare(value: &str) -> Result<Money, MoneyError> {
+        let widest = CURRENCIES
+            .iter()
+            .max_by_key(|c| c.exponent)
+            .copied()
+            .unwrap_or_default();
+        Money::parse(value, widest).map_err(|err| match err {
+            MoneyError::TooPrecise { value, .. } => MoneyError::Invalid(value),
+            err => err,
+        })
+    }
+
+    /// Compares face values whatever the currencies, so `12.50 USD` equals `12.5` parsed
+    /// with [`Money::parse_bare`].
+    pub fn cmp_value(&self, other: &Money) -> Ordering {
+        let exponent = self.currency.exponent.max(other.currency.exponent);
+        let scaled = |m: &Money| m.minor as i128 * 10_i128.pow(exponent - m.currency.exponent);
+        scaled(self).cmp(&scaled(other))
+    }
+
+    /// Adds an amount in the same currency, or returns `None` if the sum does not fit.
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        assert_eq!(
+            self.currency, other.currency,
+            "cannot add amounts in different currencies"
+        );
+        Some(Money {
+            minor: self.minor.checked_add(other.minor)?,
+            currency: self.currency,
+        })
+    }
+
+    /// Parses amounts written by older versions, which stored `f32` values that may carry
+    /// float noise such as `0.30000001`. These are rounded to the nearest minor unit.
+    pub fn parse_legacy(value: &str, currency: Currency) -> Result<Money, MoneyError> {
+        match Money::parse(value, currency) {
+            Err(MoneyError::TooPrecise { .. }) => {
+                let float: f64 = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| MoneyError::Invalid(value.to_string()))?;
+                let minor = (float * currency.scale() as f64).round();
+                if !minor.is_finite() || minor.abs() >= i64::MAX as f64 {
+                    return Err(MoneyError::Overflow(value.to_string()));
+                }
+                Ok(Money {
+                    minor: minor as i64,
+                    currency,
+                })
+            }
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = self.currency.scale();
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let abs = self.minor.unsigned_abs();
+        let text = if self.currency.exponent() == 0 {
+            format!("{}{}", sign, abs)
+        } else {
+            format!(
+                "{}{}.{:0width$}",
+                sign,
+                abs / scale as u64,
+                abs % scale as u64,
+                width = self.currency.exponent() as usize
+            )
+        };
+        f.pad(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        Currency::from_code(code).unwrap()
+    }
+
+    #[test]
+    fn parse_keeps_exact_minor_units() {
+        assert_eq!(Money::parse("12.5", Currency::USD).unwrap().minor(), 1250);
+        assert_eq!(Money::parse("-0.07", Currency::USD).unwrap().minor(), -7);
+        assert_eq!(Money::parse(".25", Currency::USD).unwrap().minor(), 25);
+        assert_eq!(
+            Money::parse("1500.00", currency("JPY")).unwrap().minor(),
+            1500
+        );
+    }
+
+    #[test]
+    fn parse_rejects_too_many_decimal_places() {
+        for (value, code) in [("1.234", "USD"), ("1.5", "JPY"), ("0.0001", "BHD")] {
+            assert!(matches!(
+                Money::parse(value, currency(code)),
+                Err(MoneyError::TooPrecise { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_amounts() {
+        for value in [
+            "", "-", ".", "1.", "-.", "1.2.3", "1e3", "+1", "12,50", "abc",
+        ] {
+            assert_eq!(
+                Money::parse(value, Currency::USD),
+                Err(MoneyError::Invalid(value.to_string())),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reports_overflow_at_the_i64_limit() {
+        assert_eq!(
+            Money::parse("92233720368547758.07", Currency::USD)
+                .unwrap()
+                .minor(),
+            i64::MAX
+        );
+        assert!(matches!(
+            Money::parse("92233720368547758.08", Currency::USD),
+            Err(MoneyError::Overflow(_))
+        ));
+        assert!(matches!(
+            Money::parse("9223372036854775808", currency("JPY")),
+            Err(MoneyError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn parse_legacy_rounds_float_noise() {
+        let usd = Currency::USD;
+        assert_eq!(Money::parse_legacy("0.30000001", usd).unwrap().minor(), 30);
+        assert_eq!(Money::parse_legacy("12.345001", usd).unwrap().minor(), 1235);
+        assert_eq!(
+            Money::parse_legacy("-2.0049999", usd).unwrap().minor(),
+            -200
+        );
+        assert_eq!(Money::parse_legacy("4.5", usd).unwrap().minor(), 450);
+        assert!(Money::parse_legacy("1e400", usd).is_err());
+    }
+
+    #[test]
+    fn display_uses_the_currency_exponent() {
+        let jpy = currency("JPY");
+        let bhd = currency("BHD");
+        assert_eq!(Money::from_minor(1500, jpy).to_string(), "1500");
+        assert_eq!(Money::from_minor(-7, jpy).to_string(), "-7");
+        assert_eq!(Money::from_minor(1234, bhd).to_string(), "1.234");
+        assert_eq!(Money::from_minor(-5, bhd).to_string(), "-0.005");
+        assert_eq!(Money::from_minor(1050, Currency::USD).to_string(), "10.50");
+        assert_eq!(
+            format!("{:>8}", Money::from_minor(5, Currency::USD)),
+            "    0.05"
+        );
+    }
+}
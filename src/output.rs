@@ -0,0 +1,161 @@
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl OutputFormat {
+    pub const NAMES: &[&str] = &["table", "csv", "tsv", "json"];
+
+    pub fn parse(name: &str) -> Result<OutputFormat, String> {
+        match name.trim().to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unknown output format \"{}\", expected one of {}",
+                name,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+
+    pub fn is_table(&self) -> bool {
+        *self == OutputFormat::Table
+    }
+
+    /// Field delimiter for the delimited formats.
+    pub fn delimiter(&self) -> Option<u8> {
+        match self {
+            OutputFormat::Table | OutputFormat::Json => None,
+            OutputFormat::Csv => Some(b','),
+            OutputFormat::Tsv => Some(b'\t'),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+        };
+        f.write_str(name)
+    }
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Sets the format every command writes in; later calls have no effect.
+pub fn init(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn current() -> OutputFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Prints a `#` status line. The machine-readable formats keep stdout for data, so there
+/// the line goes to stderr instead.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if $crate::output::current().is_table() {
+            println!($($arg)*);
+        } else {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use note;
+
+/// Records with named fields, written as CSV or TSV with a header line, or as JSON
+/// objects.
+#[derive(Debug, Default)]
+pub struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    pub fn new<S: Into<String>>(columns: impl IntoIterator<Item = S>) -> Rows {
+        Rows {
+            columns: columns.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// A single record built from field names and values.
+    pub fn record<const N: usize>(fields: [(&str, Value); N]) -> Rows {
+        let (columns, values): (Vec<&str>, Vec<Value>) = fields.into_iter().unzip();
+        let mut rows = Rows::new(columns);
+        rows.push(values);
+        rows
+    }
+
+    pub fn push(&mut self, values: Vec<Value>) {
+        debug_assert_eq!(values.len(), self.columns.len());
+        self.rows.push(values);
+    }
+
+    /// Writes the records to stdout. JSON gets an array of objects, or with `single` just
+    /// the first object (`null` if there is none).
+    pub fn write(&self, format: OutputFormat, single: bool) -> Result<(), String> {
+        match format.delimiter() {
+            Some(delimiter) => self.write_delimited(delimiter),
+            None => {
+                let mut objects = self.rows.iter().map(|row| {
+                    let object: Map<String, Value> = self
+                        .columns
+                        .iter()
+                        .cloned()
+                        .zip(row.iter().cloned())
+                        .collect();
+                    Value::Object(object)
+                });
+                let value = if single {
+                    objects.next().unwrap_or(Value::Null)
+                } else {
+                    Value::Array(objects.collect())
+                };
+                let mut stdout = io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &value)
+                    .map_err(|e| e.to_string())
+                    .and_then(|()| writeln!(stdout).map_err(|e| e.to_string()))
+            }
+        }
+    }
+
+    fn write_delimited(&self, delimiter: u8) -> Result<(), String> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(io::stdout());
+        let mut result = writer.write_record(&self.columns);
+        for row in &self.rows {
+            result = result.and_then(|()| writer.write_record(row.iter().map(field)));
+        }
+        result
+            .and_then(|()| writer.flush().map_err(csv::Error::from))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// A JSON value as one delimited field: lists are joined with commas and null is empty.
+fn field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(field).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,275 @@
+use crate::Expense;
+use crate::dates::{DateRange, RangeOptions};
+use crate::money::Money;
+use crate::tags::TagExpr;
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+
+/// Text to look for in descriptions: `/PATTERN/` is a regular expression, anything else is
+/// matched as a substring. Both ignore case.
+#[derive(Debug, Clone)]
+pub enum Search {
+    Text(String),
+    Pattern(Regex),
+}
+
+impl Search {
+    pub fn parse(input: &str) -> Result<Search, String> {
+        match input
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            Some(pattern) => RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map(Search::Pattern)
+                .map_err(|e| format!("invalid pattern \"{}\": {}", pattern, e)),
+            None => Ok(Search::Text(input.to_lowercase())),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Search::Text(needle) => text.to_lowercase().contains(needle),
+            Search::Pattern(pattern) => pattern.is_match(text),
+        }
+    }
+}
+
+/// Which expenses a command applies to. Every condition that is set must match, and
+/// expenses in the trash never do.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub range: DateRange,
+    pub category: Option<String>,
+    pub search: Option<Search>,
+    pub tags: Option<TagExpr>,
+    pub min: Option<Money>,
+    pub max: Option<Money>,
+}
+
+impl Filter {
+    /// Whether the filter selects every expense.
+    pub fn is_empty(&self) -> bool {
+        self.range.is_unbounded()
+            && self.category.is_none()
+            && self.search.is_none()
+            && self.tags.is_none()
+            && self.min.is_none()
+            && self.max.is_none()
+    }
+
+    pub fn matches(&self, expense: &Expense) -> bool {
+        !expense.is_trashed()
+            && self.range.contains(expense.date)
+            && self
+                .category
+                .as_ref()
+                .is_none_or(|c| expense.category == *c)
+            && self
+                .search
+                .as_ref()
+                .is_none_or(|search| search.matches(&expense.description))
+            && self
+                .tags
+                .as_ref()
+                .is_none_or(|expr| expr.matches(&expense.tags))
+            && self
+                .min
+                .is_none_or(|min| expense.amount.cmp_value(&min) != Ordering::Less)
+            && self
+                .max
+                .is_none_or(|max| expense.amount.cmp_value(&max) != Ordering::Greater)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Id,
+    Date,
+    /// By face value, whatever the currency.
+    Amount,
+}
+
+impl SortKey {
+    pub fn parse(name: &str) -> Result<SortKey, String> {
+        match name.trim().to_lowercase().as_str() {
+            "id" => Ok(SortKey::Id),
+            "date" => Ok(SortKey::Date),
+            "amount" => Ok(SortKey::Amount),
+            _ => Err(format!(
+                "unknown sort key \"{}\", expected date, amount or id",
+                name
+            )),
+        }
+    }
+}
+
+/// A filter plus the order and number of expenses to show.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub filter: Filter,
+    pub sort: SortKey,
+    pub reverse: bool,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn run<'a>(&self, expenses: &'a [Expense]) -> Vec<&'a Expense> {
+        let mut selected: Vec<&Expense> =
+            expenses.iter().filter(|e| self.filter.matches(e)).collect();
+        // Stable sorts, so ties stay in ID order.
+        match self.sort {
+            SortKey::Id => selected.sort_by_key(|e| e.id),
+            SortKey::Date => selected.sort_by_key(|e| e.date),
+            SortKey::Amount => selected.sort_by(|a, b| a.amount.cmp_value(&b.amount)),
+        }
+        if self.reverse {
+            selected.reverse();
+        }
+        if let Some(limit) = self.limit {
+            selected.truncate(limit);
+        }
+        selected
+    }
+}
+
+/// Why the filter flags could not be resolved, split by the kind of flag so callers can
+/// report each with its usual error.
+#[derive(Debug)]
+pub enum QueryError {
+    Range(String),
+    Tags(String),
+    Invalid(String),
+}
+
+/// Collects the flags that select expenses: the date-range flags plus `--category NAME`,
+/// `--search TEXT`, `--tag EXPR`, `--min AMOUNT` and `--max AMOUNT`. Amount bounds are
+/// compared with each expense in its own currency.
+#[derive(Debug, Default)]
+pub struct FilterOptions {
+    range: RangeOptions,
+    category: Option<String>,
+    search: Option<String>,
+    tags: Option<String>,
+    min: Option<String>,
+    max: Option<String>,
+}
+
+impl FilterOptions {
+    /// Consumes `flag` (and `value`, when the flag takes one) if it is a filter flag,
+    /// returning how many arguments were used.
+    pub fn take(&mut self, flag: &str, value: Option<&String>) -> usize {
+        let slot = match flag {
+            "--category" => &mut self.category,
+            "--search" => &mut self.search,
+            "--tag" => &mut self.tags,
+            "--min" => &mut self.min,
+            "--max" => &mut self.max,
+            _ => return self.range.take(flag, value),
+        };
+        match value {
+            Some(value) => {
+                *slot = Some(value.clone());
+                2
+            }
+            None => 0,
+        }
+    }
+
+    /// Resolves the flags relative to `today`. The category is taken as given; callers
+    /// resolve aliases.
+    pub fn resolve(&self, today: NaiveDate) -> Result<Filter, QueryError> {
+        let amount = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| {
+                    Money::parse_bare(value).map_err(|e| QueryError::Invalid(e.to_string()))
+                })
+                .transpose()
+        };
+        let filter = Filter {
+            range: self.range.resolve(today).map_err(QueryError::Range)?,
+            category: self.category.clone(),
+            search: self
+                .search
+                .as_deref()
+                .map(Search::parse)
+                .transpose()
+                .map_err(QueryError::Invalid)?,
+            tags: self
+                .tags
+                .as_deref()
+                .map(TagExpr::parse)
+                .transpose()
+                .map_err(QueryError::Tags)?,
+            min: amount(&self.min)?,
+            max: amount(&self.max)?,
+        };
+        if let (Some(min), Some(max)) = (filter.min, filter.max)
+            && min.cmp_value(&max) == Ordering::Greater
+        {
+            return Err(QueryError::Invalid(
+                "--min is greater than --max".to_string(),
+            ));
+        }
+        Ok(filter)
+    }
+}
+
+/// The filter flags plus `--sort date|amount|id`, `--reverse` and `--limit N`.
+#[derive(Debug, Default)]
+pub struct QueryOptions {
+    filter: FilterOptions,
+    sort: Option<String>,
+    reverse: bool,
+    limit: Option<String>,
+}
+
+impl QueryOptions {
+    pub fn take(&mut self, flag: &str, value: Option<&String>) -> usize {
+        let slot = match flag {
+            "--reverse" => {
+                self.reverse = true;
+                return 1;
+            }
+            "--sort" => &mut self.sort,
+            "--limit" => &mut self.limit,
+            _ => return self.filter.take(flag, value),
+        };
+        match value {
+            Some(value) => {
+                *slot = Some(value.clone());
+                2
+            }
+            None => 0,
+        }
+    }
+
+    pub fn resolve(&self, today: NaiveDate) -> Result<Query, QueryError> {
+        let sort = self
+            .sort
+            .as_deref()
+            .map(SortKey::parse)
+            .transpose()
+            .map_err(QueryError::Invalid)?
+            .unwrap_or_default();
+        let limit = self
+            .limit
+            .as_deref()
+            .map(|limit| {
+                limit
+                    .parse::<usize>()
+                    .map_err(|_| QueryError::Invalid(format!("invalid --limit \"{}\"", limit)))
+            })
+            .transpose()?;
+        Ok(Query {
+            filter: self.filter.resolve(today)?,
+            sort,
+            reverse: self.reverse,
+            limit,
+        })
+    }
+}
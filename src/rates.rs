@@ -0,0 +1,235 @@
+use crate::atomic;
+use crate::money::{Currency, Money};
+use chrono::NaiveDate;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+const RATE_DECIMALS: usize = 8;
+const RATE_SCALE: i128 = 100_000_000;
+
+/// The number of `to` units one `from` unit bought on `date`, kept to eight decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExchangeRate {
+    pub date: NaiveDate,
+    pub from: Currency,
+    pub to: Currency,
+    scaled: i64,
+}
+
+impl ExchangeRate {
+    pub fn new(date: NaiveDate, from: Currency, to: Currency, rate: &str) -> Result<Self, String> {
+        if from == to {
+            return Err(format!("cannot record a rate from {} to itself", from));
+        }
+        let invalid = || format!("\"{}\" is not a valid exchange rate", rate);
+        let (whole, fraction) = rate.trim().split_once('.').unwrap_or((rate.trim(), ""));
+        if whole.is_empty()
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > RATE_DECIMALS {
+            return Err(format!(
+                "\"{}\" has more than {} decimal places",
+                rate, RATE_DECIMALS
+            ));
+        }
+        let scaled = format!("{}{:0<width$}", whole, fraction, width = RATE_DECIMALS)
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+        if scaled == 0 {
+            return Err(invalid());
+        }
+        Ok(ExchangeRate {
+            date,
+            from,
+            to,
+            scaled,
+        })
+    }
+
+    /// Converts `amount`, or returns `None` if the result is too large to store.
+    fn apply(&self, amount: Money, inverse: bool) -> Option<Money> {
+        let (source, target) = if inverse {
+            (self.to, self.from)
+        } else {
+            (self.from, self.to)
+        };
+        debug_assert_eq!(amount.currency(), source);
+
+        let minor = amount.minor() as i128 * target.scale() as i128;
+        let (numerator, denominator) = if inverse {
+            (
+                minor.checked_mul(RATE_SCALE)?,
+                source.scale() as i128 * self.scaled as i128,
+            )
+        } else {
+            (
+                minor.checked_mul(self.scaled as i128)?,
+                source.scale() as i128 * RATE_SCALE,
+            )
+        };
+        let minor = i64::try_from(divide_rounded(numerator, denominator)).ok()?;
+        Some(Money::from_minor(minor, target))
+    }
+}
+
+impl fmt::Display for ExchangeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.scaled as i128 / RATE_SCALE;
+        let fraction = format!("{:08}", self.scaled as i128 % RATE_SCALE);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}", whole)
+        } else {
+            write!(f, "{}.{}", whole, fraction)
+        }
+    }
+}
+
+fn divide_rounded(numerator: i128, denominator: i128) -> i128 {
+    if numerator >= 0 {
+        (numerator + denominator / 2) / denominator
+    } else {
+        -((-numerator + denominator / 2) / denominator)
+    }
+}
+
+fn parse_rate_fields(fields: &[&str]) -> Result<ExchangeRate, String> {
+    if fields.len() != 4 {
+        return Err("expected date, from currency, to currency and rate".to_string());
+    }
+    let date = NaiveDate::parse_from_str(fields[0], "%Y-%m-%d")
+        .map_err(|_| format!("\"{}\" is not a valid date", fields[0]))?;
+    let from = Currency::from_code(fields[1])
+        .ok_or_else(|| format!("unknown currency \"{}\"", fields[1]))?;
+    let to = Currency::from_code(fields[2])
+        .ok_or_else(|| format!("unknown currency \"{}\"", fields[2]))?;
+    ExchangeRate::new(date, from, to, fields[3])
+}
+
+pub struct RateTable {
+    path: PathBuf,
+    rates: Vec<ExchangeRate>,
+}
+
+impl RateTable {
+    pub fn load(path: &Path) -> Result<RateTable, String> {
+        let mut table = RateTable {
+            path: path.to_path_buf(),
+            rates: Vec::new(),
+        };
+        if !path.exists() {
+            return Ok(table);
+        }
+
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let rate = parse_rate_fields(&fields)
+                .map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))?;
+            table.insert(rate);
+        }
+        Ok(table)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut contents = String::new();
+        for rate in &self.rates {
+            contents.push_str(&format!(
+                "{} {} {} {}\n",
+                rate.date, rate.from, rate.to, rate
+            ));
+        }
+        atomic::write_file(&self.path, contents.as_bytes())
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+
+    pub fn rates(&self) -> &[ExchangeRate] {
+        &self.rates
+    }
+
+    /// Adds a rate, replacing any existing rate for the same pair on the same date.
+    pub fn insert(&mut self, rate: ExchangeRate) {
+        self.rates
+            .retain(|r| !(r.date == rate.date && r.from == rate.from && r.to == rate.to));
+        let position = self
+            .rates
+            .partition_point(|r| (r.date, r.from, r.to) < (rate.date, rate.from, rate.to));
+        self.rates.insert(position, rate);
+    }
+
+    /// Imports `date,from,to,rate` rows from a CSV file. A leading header row is skipped.
+    pub fn import_csv(&mut self, path: &Path) -> Result<usize, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut imported = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| format!("{}: {}", path.display(), e))?;
+            let fields: Vec<&str> = record.iter().collect();
+            if fields.iter().all(|f| f.is_empty()) {
+                continue;
+            }
+            if index == 0 && NaiveDate::parse_from_str(fields[0], "%Y-%m-%d").is_err() {
+                continue;
+            }
+            let rate = parse_rate_fields(&fields)
+                .map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))?;
+            imported.push(rate);
+        }
+
+        let count = imported.len();
+        for rate in imported {
+            self.insert(rate);
+        }
+        Ok(count)
+    }
+
+    /// Converts `amount` using the most recent rate dated on or before `date`, falling back
+    /// to the inverse of the opposite pair when no direct rate exists.
+    pub fn convert(&self, amount: Money, to: Currency, date: NaiveDate) -> Result<Money, String> {
+        let from = amount.currency();
+        if from == to {
+            return Ok(amount);
+        }
+
+        let rate = self
+            .rates
+            .iter()
+            .filter(|r| r.date <= date)
+            .filter_map(|r| {
+                if r.from == from && r.to == to {
+                    Some((r, false))
+                } else if r.from == to && r.to == from {
+                    Some((r, true))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(r, inverse)| (r.date, !inverse));
+
+        match rate {
+            Some((rate, inverse)) => rate
+                .apply(amount, inverse)
+                .ok_or_else(|| format!("{} {} is too large to convert to {}", amount, from, to)),
+            None => Err(format!(
+                "no {} to {} exchange rate on or before {}",
+                from, to, date
+            )),
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::Expense;
+use crate::categories::{UNCATEGORIZED, validate_name};
+use crate::money::{Currency, Money};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use std::collections::BTreeSet;
+use std::fmt;
+
+pub const FORMAT_VERSION: u32 = 3;
+
+const HEADER_PREFIX: &str = "# rusty-expense-tracker ledger v";
+const FIELDS: [&str; 8] = [
+    "id",
+    "date",
+    "amount",
+    "currency",
+    "category",
+    "tags",
+    "description",
+    "deleted",
+];
+
+/// A line of a ledger file that could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadError {
+    pub file: String,
+    pub line: usize,
+    pub reason: String,
+    pub content: String,
+}
+
+/// The ID a ledger line starts with, if it has a readable one. Unreadable lines keep
+/// their ID reserved so fixing and restoring them later cannot clash with new expenses.
+pub fn leading_id(line: &str) -> Option<i32> {
+    line.split_whitespace()
+        .next()
+        .and_then(|id| parse_id(id).ok())
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.reason)
+    }
+}
+
+/// The first line of a current ledger. `next_id` is the lowest ID never handed out, kept so
+/// that purging the newest expenses from the trash does not free their IDs for reuse.
+pub fn header(next_id: i32) -> String {
+    format!("{}{} next_id={}", HEADER_PREFIX, FORMAT_VERSION, next_id)
+}
+
+/// What a ledger's first line declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    /// Missing from ledgers written before the header carried it.
+    pub next_id: Option<i32>,
+}
+
+/// Reads a ledger's first line, treating files written before the header existed as
+/// version 1.
+pub fn parse_header(first_line: &str) -> Result<Header, String> {
+    let Some(rest) = first_line.trim_end().strip_prefix(HEADER_PREFIX) else {
+        return Ok(Header {
+            version: 1,
+            next_id: None,
+        });
+    };
+    let mut words = rest.split(' ');
+    let version = words.next().unwrap_or_default();
+    let version = match version.parse::<u32>() {
+        Ok(version) if (2..=FORMAT_VERSION).contains(&version) => version,
+        _ => {
+            return Err(format!(
+                "unsupported ledger format version \"{}\", this build reads up to v{}",
+                version, FORMAT_VERSION
+            ));
+        }
+    };
+    let mut next_id = None;
+    for field in words {
+        match field.split_once('=') {
+            Some(("next_id", id)) => next_id = Some(parse_id(id)?),
+            _ => return Err(format!("unexpected \"{}\" in the ledger header", field)),
+        }
+    }
+    Ok(Header { version, next_id })
+}
+
+/// Escapes backslashes, tabs and line breaks so a value fits in one tab-separated field.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(value: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => return Err(format!("unknown escape sequence \"\\{}\"", other)),
+            None => return Err("line ends with a lone backslash".to_string()),
+        }
+    }
+    Ok(unescaped)
+}
+
+pub fn encode(expense: &Expense) -> String {
+    let tags: Vec<&str> = expense.tags.iter().map(String::as_str).collect();
+    [
+        expense.id.to_string(),
+        expense.date.to_string(),
+        expense.amount.to_string(),
+        expense.amount.currency().to_string(),
+        escape(&expense.category),
+        escape(&tags.join(",")),
+        escape(&expense.description),
+        expense.deleted.map_or(String::new(), format_timestamp),
+    ]
+    .join("\t")
+}
+
+pub fn format_timestamp(time: DateTime<FixedOffset>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+pub fn parse_timestamp(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(value).map_err(|_| format!("invalid timestamp \"{}\"", value))
+}
+
+/// Reads a record of the current format, or of v2, which had no deletion timestamp.
+pub fn decode(line: &str) -> Result<Expense, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != FIELDS.len() && fields.len() != FIELDS.len() - 1 {
+        return Err(format!(
+            "expected {} tab-separated fields ({}), found {}",
+            FIELDS.len(),
+            FIELDS.join(", "),
+            fields.len()
+        ));
+    }
+
+    let id = parse_id(fields[0])?;
+    let date = parse_date(fields[1])?;
+    let currency = Currency::from_code(fields[3])
+        .ok_or_else(|| format!("unknown currency \"{}\"", fields[3]))?;
+    let amount = Money::parse(fields[2], currency).map_err(|e| e.to_string())?;
+    let category = validate_name(&unescape(fields[4])?)?;
+    let tags = unescape(fields[5])?
+        .split(',')
+        .filter(|t| !t.is_empty())
+        .map(validate_name)
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    let description = unescape(fields[6])?;
+    let deleted = match fields.get(7) {
+        Some(time) if !time.is_empty() => Some(parse_timestamp(time)?),
+        _ => None,
+    };
+
+    Ok(Expense {
+        id,
+        date,
+        description,
+        amount,
+        category,
+        tags,
+        deleted,
+    })
+}
+
+/// Reads the unversioned `ID DATE DESCRIPTION|AMOUNT [CURRENCY [CATEGORY [TAGS]]]` lines
+/// written by earlier versions, including rows whose amounts were stored as floats.
+pub fn decode_legacy(line: &str) -> Result<Expense, String> {
+    let parts: Vec<&str> = line.trim().splitn(3, ' ').collect();
+    if parts.len() < 3 {
+        return Err("expected \"ID DATE DESCRIPTION|AMOUNT\"".to_string());
+    }
+
+    let id = parse_id(parts[0])?;
+    let date = parse_date(parts[1])?;
+    let Some((description, amount_part)) = parts[2].rsplit_once('|') else {
+        return Err("missing \"|\" between description and amount".to_string());
+    };
+
+    let mut amount_parts = amount_part.split_whitespace();
+    let amount_text = amount_parts.next().unwrap_or("");
+    let currency = match amount_parts.next() {
+        Some(code) => {
+            Currency::from_code(code).ok_or_else(|| format!("unknown currency \"{}\"", code))?
+        }
+        None => Currency::default(),
+    };
+    let amount = Money::parse_legacy(amount_text, currency).map_err(|e| e.to_string())?;
+    let category = validate_name(amount_parts.next().unwrap_or(UNCATEGORIZED))?;
+    let tags = amount_parts
+        .next()
+        .map(|t| t.split(',').map(validate_name).collect())
+        .transpose()?
+        .unwrap_or_default();
+    if let Some(extra) = amount_parts.next() {
+        return Err(format!("unexpected \"{}\" after the tags", extra));
+    }
+
+    Ok(Expense {
+        id,
+        date,
+        description: description.trim().to_string(),
+        amount,
+        category,
+        tags,
+        deleted: None,
+    })
+}
+
+fn parse_id(value: &str) -> Result<i32, String> {
+    value
+        .parse::<i32>()
+        .ok()
+        .filter(|id| *id > 0)
+        .ok_or_else(|| format!("invalid ID \"{}\"", value))
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date \"{}\", expected YYYY-MM-DD", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expense(description: &str, deleted: Option<&str>) -> Expense {
+        Expense {
+            id: 7,
+            date: NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+            description: description.to_string(),
+            amount: Money::parse("12.50", Currency::from_code("EUR").unwrap()).unwrap(),
+            category: "food".to_string(),
+            tags: ["work".to_string(), "trip".to_string()].into(),
+            deleted: deleted.map(|time| parse_timestamp(time).unwrap()),
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let descriptions = [
+            "Lunch",
+            "tab\there",
+            "two\nlines\r\nand more",
+            "back\\slash \\t not a tab",
+            "pipe | and trailing \\",
+            "",
+        ];
+        for description in descriptions {
+            for deleted in [None, Some("2024-03-01T09:30:00+01:00")] {
+                let original = expense(description, deleted);
+                let line = encode(&original);
+                assert!(!line.contains('\n') && !line.contains('\r'), "{:?}", line);
+                assert_eq!(line.split('\t').count(), FIELDS.len());
+                assert_eq!(decode(&line), Ok(original));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_reads_v2_records_without_a_deletion_time() {
+        let line = "3\t2023-12-31\t4.05\tUSD\tuncategorized\t\tParking\\tlot";
+        let expense = decode(line).unwrap();
+        assert_eq!(expense.id, 3);
+        assert_eq!(expense.amount.minor(), 405);
+        assert_eq!(expense.description, "Parking\tlot");
+        assert!(expense.tags.is_empty());
+        assert_eq!(expense.deleted, None);
+    }
+
+    #[test]
+    fn decode_rejects_bad_fields() {
+        let valid = encode(&expense("Lunch", None));
+        let with_description = |description: &str| valid.replace("Lunch", description);
+        assert_eq!(
+            decode(&with_description("a\\qb")),
+            Err("unknown escape sequence \"\\q\"".to_string())
+        );
+        assert_eq!(
+            decode(&with_description("ends with \\")),
+            Err("line ends with a lone backslash".to_string())
+        );
+        assert!(decode(&valid.replace("12.50", "12.505")).is_err());
+        assert!(decode(&format!("{}\textra", valid)).is_err());
+    }
+
+    #[test]
+    fn header_round_trips_next_id() {
+        assert_eq!(
+            parse_header(&header(42)),
+            Ok(Header {
+                version: FORMAT_VERSION,
+                next_id: Some(42),
+            })
+        );
+        assert_eq!(
+            parse_header("# rusty-expense-tracker ledger v2"),
+            Ok(Header {
+                version: 2,
+                next_id: None,
+            })
+        );
+        assert_eq!(parse_header("1 2024-01-01 Tea|1.00").unwrap().version, 1);
+        assert!(parse_header("# rusty-expense-tracker ledger v9").is_err());
+        assert!(parse_header("# rusty-expense-tracker ledger v3 next_id=0").is_err());
+        assert!(parse_header("# rusty-expense-tracker ledger v3 extra").is_err());
+    }
+
+    #[test]
+    fn decode_legacy_reads_baseline_lines() {
+        let expense = decode_legacy("12 2023-05-04 Coffee | beans|0.30000001").unwrap();
+        assert_eq!(expense.id, 12);
+        assert_eq!(expense.date, NaiveDate::from_ymd_opt(2023, 5, 4).unwrap());
+        assert_eq!(expense.description, "Coffee | beans");
+        assert_eq!(expense.amount, Money::from_minor(30, Currency::USD));
+        assert_eq!(expense.category, UNCATEGORIZED);
+        assert!(expense.tags.is_empty());
+
+        let expense = decode_legacy("4 2023-05-04 Sushi|1500 JPY food work,trip").unwrap();
+        assert_eq!(expense.amount.to_string(), "1500");
+        assert_eq!(expense.amount.currency().to_string(), "JPY");
+        assert_eq!(expense.category, "food");
+        assert_eq!(expense.tags.len(), 2);
+
+        assert!(decode_legacy("4 2023-02-30 Bad date|1.00").is_err());
+        assert!(decode_legacy("4 2023-05-04 No amount").is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use crate::Expense;
+use crate::categories::validate_name;
+use crate::dates::DateRange;
+use crate::money::{Currency, Money};
+use crate::record::{self, LoadError};
+use crate::storage::{Loaded, Storage};
+use chrono::NaiveDate;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row, params};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+const SCHEMA_VERSION: i32 = 3;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS expenses (
+        id          INTEGER PRIMARY KEY,
+        date        TEXT    NOT NULL,
+        amount      INTEGER NOT NULL,
+        currency    TEXT    NOT NULL,
+        category    TEXT    NOT NULL,
+        tags        TEXT    NOT NULL DEFAULT '',
+        description TEXT    NOT NULL,
+        deleted_at  TEXT
+    );
+    CREATE INDEX IF NOT EXISTS expenses_by_date ON expenses (date);
+    CREATE INDEX IF NOT EXISTS expenses_by_category ON expenses (category, date);
+    CREATE TABLE IF NOT EXISTS metadata (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const COLUMNS: &str = "id, date, amount, currency, category, tags, description, deleted_at";
+/// Version 1 databases predate the trash.
+const V1_COLUMNS: &str = "id, date, amount, currency, category, tags, description, NULL";
+
+/// A ledger kept in an embedded SQLite database. Amounts are stored in minor units and
+/// dates as `YYYY-MM-DD` text so that the date index sorts chronologically.
+pub struct SqliteStorage {
+    path: PathBuf,
+    /// `None` until the first save creates the database.
+    conn: Option<Connection>,
+    /// The schema version on disk, 0 before the schema exists. Reads work with any
+    /// supported version; the schema is only created or upgraded by a save, so read-only
+    /// commands never write to the file.
+    version: i32,
+    /// What the database held after the last full load or save, so a save only writes the
+    /// rows that changed.
+    stored: HashMap<i32, Expense>,
+    unreadable: Vec<i64>,
+}
+
+impl SqliteStorage {
+    /// Opens an existing database without changing it, or prepares to create one on the
+    /// first save if `path` does not exist yet.
+    pub fn open(path: &Path) -> Result<SqliteStorage, String> {
+        let mut storage = SqliteStorage {
+            path: path.to_path_buf(),
+            conn: None,
+            version: 0,
+            stored: HashMap::new(),
+            unreadable: Vec::new(),
+        };
+        if !path.exists() {
+            return Ok(storage);
+        }
+
+        let error = |e: rusqlite::Error| format!("{}: {}", path.display(), e);
+        let flags = OpenFlags::default() - OpenFlags::SQLITE_OPEN_CREATE;
+        let conn = Connection::open_with_flags(path, flags).map_err(error)?;
+        let version: i32 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(error)?;
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "{}: unsupported database schema version {}, this build reads up to v{}",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        storage.conn = Some(conn);
+        storage.version = version;
+        Ok(storage)
+    }
+
+    /// The stored high-water mark for IDs. Databases before version 3 have none.
+    fn next_id(&self) -> Result<Option<i32>, String> {
+        let Some(conn) = self.conn.as_ref().filter(|_| self.version >= 3) else {
+            return Ok(None);
+        };
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'next_id'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        value
+            .map(|value| {
+                value
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|id| *id > 0)
+                    .ok_or_else(|| {
+                        format!("{}: invalid next_id \"{}\"", self.path.display(), value)
+                    })
+            })
+            .transpose()
+    }
+
+    fn columns(&self) -> &'static str {
+        if self.version == 1 {
+            V1_COLUMNS
+        } else {
+            COLUMNS
+        }
+    }
+
+    fn select(&mut self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Loaded, String> {
+        self.unreadable.clear();
+        let Some(conn) = self.conn.as_ref().filter(|_| self.version > 0) else {
+            return Ok(Loaded::default());
+        };
+        let file = self.path.display().to_string();
+        let error = |e: rusqlite::Error| format!("{}: {}", file, e);
+        let mut statement = conn.prepare(sql).map_err(error)?;
+        let rows = statement
+            .query_map(params, |row| Ok((row.get::<_, i64>(0)?, decode_row(row)?)))
+            .map_err(error)?;
+
+        let mut loaded = Loaded::default();
+        for row in rows {
+            let (id, decoded) = row.map_err(error)?;
+            match decoded {
+                Ok(expense) => loaded.expenses.push(expense),
+                Err((reason, content)) => {
+                    self.unreadable.push(id);
+                    loaded.diagnostics.push(LoadError {
+                        file: file.clone(),
+                        line: id as usize,
+                        reason,
+                        content,
+                    });
+                }
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Loaded, String> {
+        let sql = format!("SELECT {} FROM expenses ORDER BY id", self.columns());
+        let mut loaded = self.select(&sql, &[])?;
+        loaded.next_id = self.next_id()?;
+        self.stored = loaded.expenses.iter().map(|e| (e.id, e.clone())).collect();
+        Ok(loaded)
+    }
+
+    fn query(&mut self, range: &DateRange, category: Option<&str>) -> Result<Loaded, String> {
+        let from = range.from.map(|date| date.to_string());
+        let to = range.to.map(|date| date.to_string());
+        let sql = format!(
+            "SELECT {} FROM expenses
+             WHERE (?1 IS NULL OR date >= ?1)
+               AND (?2 IS NULL OR date <= ?2)
+               AND (?3 IS NULL OR category = ?3)
+               AND {}
+             ORDER BY id",
+            self.columns(),
+            if self.version == 1 {
+                "1"
+            } else {
+                "deleted_at IS NULL"
+            }
+        );
+        self.select(&sql, &[&from, &to, &category])
+    }
+
+    fn save(&mut self, expenses: &[Expense], next_id: i32) -> Result<(), String> {
+        let path = self.path.display().to_string();
+        let error = |e: rusqlite::Error| format!("{}: {}", path, e);
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => Connection::open(&self.path).map_err(error)?,
+        };
+        let tx = self.conn.insert(conn).transaction().map_err(error)?;
+        if self.version < SCHEMA_VERSION {
+            tx.execute_batch(SCHEMA).map_err(error)?;
+            if self.version == 1 {
+                tx.execute_batch("ALTER TABLE expenses ADD COLUMN deleted_at TEXT")
+                    .map_err(error)?;
+            }
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)
+                .map_err(error)?;
+        }
+        {
+            let mut delete = tx
+                .prepare("DELETE FROM expenses WHERE id = ?1")
+                .map_err(error)?;
+            for id in &self.unreadable {
+                delete.execute([id]).map_err(error)?;
+            }
+            let current: BTreeSet<i32> = expenses.iter().map(|e| e.id).collect();
+            for id in self.stored.keys().filter(|id| !current.contains(id)) {
+                delete.execute([id]).map_err(error)?;
+            }
+
+            let mut upsert = tx
+                .prepare(&format!(
+                    "INSERT OR REPLACE INTO expenses ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    COLUMNS
+                ))
+                .map_err(error)?;
+            for e in expenses {
+                if self.stored.get(&e.id) == Some(e) {
+                    continue;
+                }
+                let tags: Vec<&str> = e.tags.iter().map(String::as_str).collect();
+                upsert
+                    .execute(params![
+                        e.id,
+                        e.date.to_string(),
+                        e.amount.minor(),
+                        e.amount.currency().to_string(),
+                        e.category,
+                        tags.join(","),
+                        e.description,
+                        e.deleted.map(record::format_timestamp),
+                    ])
+                    .map_err(error)?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES ('next_id', ?1)",
+            [next_id.to_string()],
+        )
+        .map_err(error)?;
+        tx.commit().map_err(error)?;
+
+        self.version = SCHEMA_VERSION;
+        self.unreadable.clear();
+        self.stored = expenses.iter().map(|e| (e.id, e.clone())).collect();
+        Ok(())
+    }
+}
+
+/// Turns a row into an expense, or into the reason it is invalid along with its raw
+/// contents for the quarantine file.
+fn decode_row(row: &Row) -> rusqlite::Result<Result<Expense, (String, String)>> {
+    let id: i64 = row.get(0)?;
+    let date: String = row.get(1)?;
+    let amount: i64 = row.get(2)?;
+    let currency: String = row.get(3)?;
+    let category: String = row.get(4)?;
+    let tags: String = row.get(5)?;
+    let description: String = row.get(6)?;
+    let deleted: Option<String> = row.get(7)?;
+
+    let decoded = (|| {
+        let id = i32::try_from(id)
+            .ok()
+            .filter(|id| *id > 0)
+            .ok_or_else(|| format!("invalid ID \"{}\"", id))?;
+        let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .map_err(|_| format!("invalid date \"{}\", expected YYYY-MM-DD", date))?;
+        let currency = Currency::from_code(&currency)
+            .ok_or_else(|| format!("unknown currency \"{}\"", currency))?;
+        let tags = tags
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(validate_name)
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        Ok(Expense {
+            id,
+            date,
+            description: description.clone(),
+            amount: Money::from_minor(amount, currency),
+            category: validate_name(&category)?,
+            tags,
+            deleted: deleted
+                .as_deref()
+                .map(record::parse_timestamp)
+                .transpose()?,
+        })
+    })();
+
+    // Written like a ledger record, so the quarantined line reads the same way as one from
+    // a text ledger; an amount whose currency is unknown stays in labelled minor units.
+    Ok(decoded.map_err(|reason| {
+        let amount = match Currency::from_code(&currency) {
+            Some(currency) => Money::from_minor(amount, currency).to_string(),
+            None => format!("amount_minor={}", amount),
+        };
+        let content = [
+            id.to_string(),
+            record::escape(&date),
+            amount,
+            record::escape(&currency),
+            record::escape(&category),
+            record::escape(&tags),
+            record::escape(&description),
+            record::escape(deleted.as_deref().unwrap_or_default()),
+        ]
+        .join("\t");
+        (reason, content)
+    }))
+}
@@ -0,0 +1,198 @@
+use crate::Expense;
+use crate::atomic;
+use crate::dates::DateRange;
+use crate::output::note;
+use crate::record::{self, LoadError};
+use crate::sqlite::SqliteStorage;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// The expenses read from a ledger, plus the entries that could not be read.
+#[derive(Debug, Default)]
+pub struct Loaded {
+    pub expenses: Vec<Expense>,
+    pub diagnostics: Vec<LoadError>,
+    /// The lowest ID the ledger has never used, if it records one.
+    pub next_id: Option<i32>,
+}
+
+/// Where a ledger's expenses live between invocations.
+pub trait Storage {
+    fn load(&mut self) -> Result<Loaded, String>;
+
+    /// Reads only the expenses dated within `range` and, if given, filed under `category`,
+    /// leaving out those in the trash.
+    fn query(&mut self, range: &DateRange, category: Option<&str>) -> Result<Loaded, String>;
+
+    /// Replaces the stored expenses with `expenses` and records `next_id` as the lowest
+    /// unused ID. Entries reported as unreadable by the last load are dropped, so callers
+    /// must have dealt with them first.
+    fn save(&mut self, expenses: &[Expense], next_id: i32) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Text,
+    Sqlite,
+}
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const SQLITE_EXTENSIONS: &[&str] = &["db", "sqlite", "sqlite3"];
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Backend, String> {
+        match name.trim().to_lowercase().as_str() {
+            "text" | "txt" => Ok(Backend::Text),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!(
+                "unknown storage \"{}\", expected text or sqlite",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Text => "text",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Backend::Text => "txt",
+            Backend::Sqlite => "db",
+        }
+    }
+
+    /// Recognises SQLite ledgers by their header, or by extension for files that do not
+    /// exist yet.
+    pub fn detect(path: &Path) -> Backend {
+        let mut magic = [0u8; SQLITE_MAGIC.len()];
+        match File::open(path).and_then(|mut file| file.read_exact(&mut magic)) {
+            Ok(()) if magic == SQLITE_MAGIC => Backend::Sqlite,
+            Ok(()) => Backend::Text,
+            Err(_) if path.exists() => Backend::Text,
+            Err(_) => {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                if SQLITE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+                    Backend::Sqlite
+                } else {
+                    Backend::Text
+                }
+            }
+        }
+    }
+
+    pub fn open(&self, path: &Path) -> Result<Box<dyn Storage>, String> {
+        match self {
+            Backend::Text => Ok(Box::new(TextStorage::new(path))),
+            Backend::Sqlite => Ok(Box::new(SqliteStorage::open(path)?)),
+        }
+    }
+}
+
+/// The tab-separated ledger file, rewritten as a whole on every save.
+pub struct TextStorage {
+    path: PathBuf,
+    format_version: u32,
+}
+
+impl TextStorage {
+    pub fn new(path: &Path) -> TextStorage {
+        TextStorage {
+            path: path.to_path_buf(),
+            format_version: record::FORMAT_VERSION,
+        }
+    }
+}
+
+impl Storage for TextStorage {
+    fn load(&mut self) -> Result<Loaded, String> {
+        let mut loaded = Loaded::default();
+        if !self.path.exists() {
+            return Ok(loaded);
+        }
+
+        let display_name = self.path.display().to_string();
+        let file = File::open(&self.path).map_err(|e| format!("{}: {}", display_name, e))?;
+        let reader = BufReader::new(file);
+        let mut version = record::FORMAT_VERSION;
+        let mut ids = HashSet::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("{}:{}: {}", display_name, index + 1, e))?;
+            let error = |reason: String| LoadError {
+                file: display_name.clone(),
+                line: index + 1,
+                reason,
+                content: line.clone(),
+            };
+            if index == 0 {
+                let header = record::parse_header(&line).map_err(|e| error(e).to_string())?;
+                version = header.version;
+                loaded.next_id = header.next_id;
+                if version > 1 {
+                    continue;
+                }
+            }
+            if line.trim().is_empty() || (version > 1 && line.starts_with('#')) {
+                continue;
+            }
+
+            let decoded = if version == 1 {
+                record::decode_legacy(&line)
+            } else {
+                record::decode(&line)
+            };
+            match decoded {
+                Ok(expense) if !ids.insert(expense.id) => {
+                    loaded
+                        .diagnostics
+                        .push(error(format!("duplicate ID {}", expense.id)));
+                }
+                Ok(expense) => loaded.expenses.push(expense),
+                Err(reason) => loaded.diagnostics.push(error(reason)),
+            }
+        }
+        self.format_version = version;
+        Ok(loaded)
+    }
+
+    fn query(&mut self, range: &DateRange, category: Option<&str>) -> Result<Loaded, String> {
+        let mut loaded = self.load()?;
+        loaded.expenses.retain(|e| {
+            !e.is_trashed() && range.contains(e.date) && category.is_none_or(|c| e.category == c)
+        });
+        Ok(loaded)
+    }
+
+    fn save(&mut self, expenses: &[Expense], next_id: i32) -> Result<(), String> {
+        if self.format_version < record::FORMAT_VERSION && self.path.exists() {
+            let backup = crate::sibling_path(&self.path, &format!(".v{}.bak", self.format_version));
+            if !backup.exists() {
+                fs::copy(&self.path, &backup)
+                    .map_err(|e| format!("{}: {}", backup.display(), e))?;
+                note!(
+                    "# Upgraded {} to format v{} (previous copy kept in {})",
+                    self.path.display(),
+                    record::FORMAT_VERSION,
+                    backup.display()
+                );
+            }
+        }
+
+        let mut contents = record::header(next_id);
+        contents.push('\n');
+        for expense in expenses {
+            contents.push_str(&record::encode(expense));
+            contents.push('\n');
+        }
+        atomic::write_file(&self.path, contents.as_bytes())
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.format_version = record::FORMAT_VERSION;
+        Ok(())
+    }
+}
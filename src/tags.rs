@@ -0,0 +1,149 @@
+use crate::categories::validate_name;
+use std::collections::BTreeSet;
+
+/// A boolean expression over tags, such as `work and not (reimbursed or personal)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>),
+}
+
+impl TagExpr {
+    pub fn parse(input: &str) -> Result<TagExpr, String> {
+        let tokens = tokenize(input);
+        if tokens.is_empty() {
+            return Err("empty tag expression".to_string());
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected \"{}\" in tag expression", token)),
+        }
+    }
+
+    pub fn matches(&self, tags: &BTreeSet<String>) -> bool {
+        match self {
+            TagExpr::Tag(tag) => tags.contains(tag),
+            TagExpr::Not(inner) => !inner.matches(tags),
+            TagExpr::And(left, right) => left.matches(tags) && right.matches(tags),
+            TagExpr::Or(left, right) => left.matches(tags) || right.matches(tags),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    for c in input.chars() {
+        if c == '(' || c == ')' || c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// How deeply `not` and parentheses may nest before the parser gives up, so a hostile
+/// expression cannot exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next_is(&self, keyword: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.parse_and()?;
+        while self.next_is("or") {
+            self.pos += 1;
+            expr = TagExpr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.next_is("and") {
+            self.pos += 1;
+            expr = TagExpr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<TagExpr, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("tag expression is nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let expr = self.parse_primary();
+        self.depth -= 1;
+        expr
+    }
+
+    fn parse_primary(&mut self) -> Result<TagExpr, String> {
+        let Some(token) = self.peek().map(str::to_string) else {
+            return Err("tag expression ends unexpectedly".to_string());
+        };
+        self.pos += 1;
+
+        if token.eq_ignore_ascii_case("not") {
+            return Ok(TagExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        if token == "(" {
+            let expr = self.parse_or()?;
+            if self.peek() != Some(")") {
+                return Err("missing \")\" in tag expression".to_string());
+            }
+            self.pos += 1;
+            return Ok(expr);
+        }
+        if token == ")" || token.eq_ignore_ascii_case("and") || token.eq_ignore_ascii_case("or") {
+            return Err(format!("unexpected \"{}\" in tag expression", token));
+        }
+        Ok(TagExpr::Tag(validate_name(&token)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rejects_deep_nesting() {
+        let nested = |depth: usize| format!("{}work{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(TagExpr::parse(&nested(MAX_DEPTH - 1)).is_ok());
+        for input in [nested(30_000), format!("{}work", "not ".repeat(30_000))] {
+            assert_eq!(
+                TagExpr::parse(&input),
+                Err("tag expression is nested too deeply".to_string())
+            );
+        }
+    }
+}
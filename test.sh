@@ -2,19 +2,55 @@
 
 cargo build --quiet
 
+# Keep the smoke test ledger in the working directory instead of the XDG data dir.
+export EXPENSES_FILE=expenses.txt
+
 echo -e "\n# Adding expenses..."
 cargo run --quiet -- add --description "Coffee" --amount 3.50
-cargo run --quiet -- add --description "Books" --amount 29.99
-cargo run --quiet -- add --description "Lunch" --amount 12.00
+cargo run --quiet -- add --description "Books" --amount 29.99 --category shopping --tag work --tag reimbursable
+cargo run --quiet -- add --description "Lunch" --amount 12.00 --category dining --tag work
+cargo run --quiet -- add --description "Croissant" --amount 2.80 --currency EUR --date yesterday
+
+echo -e "\n# Adding exchange rates..."
+cargo run --quiet -- rates add --date 2020-01-01 --from EUR --to USD --rate 1.10
+
+echo -e "\n# Previewing a bank statement import..."
+printf 'Date,Details,Amount\n%s,COFFEE SHOP 12,-3.50\n%s,REFUND,8.00\n' "$(date +%m/%d/%Y)" "$(date +%m/%d/%Y)" > statement.csv
+cargo run --quiet -- import csv statement.csv --date Date --description Details --amount Amount --date-format %m/%d/%Y
+rm -f statement.csv
 
 echo -e "\n# Listing expenses..."
 cargo run --quiet -- list
 
+echo -e "\n# Listing work expenses that are not reimbursable..."
+cargo run --quiet -- list --tag "work and not reimbursable"
+
+echo -e "\n# Listing the two largest expenses..."
+cargo run --quiet -- list --sort amount --reverse --limit 2
+
 echo -e "\n# Showing full summary..."
 cargo run --quiet -- summary
 
-echo -e "\n# Showing summary for July..."
-cargo run --quiet -- summary --month 7
+echo -e "\n# Showing summary for this month..."
+cargo run --quiet -- summary --this-month
+
+echo -e "\n# Showing summary for July of last year..."
+cargo run --quiet -- summary --month 7 --year $(( $(date +%Y) - 1 ))
+
+echo -e "\n# Showing summary by category..."
+cargo run --quiet -- summary --by category
+
+echo -e "\n# Showing summary converted to USD..."
+cargo run --quiet -- summary --base USD
+
+echo -e "\n# Showing monthly report by category..."
+cargo run --quiet -- report --by category --base USD
+
+echo -e "\n# Previewing a bulk re-categorization..."
+cargo run --quiet -- bulk categorize food --search coffee --this-month
+
+echo -e "\n# Editing expense with ID 3..."
+cargo run --quiet -- edit --id 3 --description "Team lunch" --amount 14.50
 
 echo -e "\n# Deleting expense with ID 2..."
 cargo run --quiet -- delete --id 2
@@ -22,3 +58,18 @@ cargo run --quiet -- delete --id 2
 echo -e "\n# Listing expenses after deletion..."
 cargo run --quiet -- list
 
+echo -e "\n# Showing the trash..."
+cargo run --quiet -- trash list
+
+
+echo -e "\n# Exporting the summary as CSV and the listing as JSON..."
+cargo run --quiet -- summary --by category --output csv
+cargo run --quiet -- list --limit 2 --output json
+
+echo -e "\n# Undoing the deletion..."
+cargo run --quiet -- undo
+cargo run --quiet -- history --limit 3
+
+echo -e "\n# Migrating the ledger to SQLite..."
+cargo run --quiet -- migrate --to sqlite --path expenses.db
+cargo run --quiet -- --file expenses.db list